@@ -0,0 +1,97 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cost of logging an event in a span with 0, 5 or 20 fields to the Bunyan
+//! JSON file, as seen by the logging thread: the file is written by the
+//! background worker.
+//!
+//! `cargo bench --bench span_fields`
+
+use std::env;
+
+use common_tracing::build_global_subscriber;
+use common_tracing::Config;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use tracing::info;
+use tracing::info_span;
+
+fn span_with_fields(n: usize) {
+    let span = match n {
+        0 => info_span!("request"),
+        5 => info_span!("request", f0 = 0, f1 = "a", f2 = 2.5, f3 = true, f4 = "e"),
+        _ => info_span!(
+            "request",
+            f0 = 0,
+            f1 = "a",
+            f2 = 2.5,
+            f3 = true,
+            f4 = "e",
+            f5 = 5,
+            f6 = "g",
+            f7 = 7.5,
+            f8 = false,
+            f9 = "j",
+            f10 = 10,
+            f11 = "l",
+            f12 = 12.5,
+            f13 = true,
+            f14 = "o",
+            f15 = 15,
+            f16 = "q",
+            f17 = 17.5,
+            f18 = false,
+            f19 = "t"
+        ),
+    };
+    let _entered = span.enter();
+    info!(rows = 3, "query done");
+}
+
+fn bench_with(c: &mut Criterion, group: &str, cfg: &Config) {
+    let (subscriber, outcome) = build_global_subscriber("bench", cfg);
+    let _guards = outcome.guards;
+
+    tracing::subscriber::with_default(subscriber, || {
+        let mut group = c.benchmark_group(group);
+        for n in [0, 5, 20] {
+            group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+                b.iter(|| span_with_fields(n))
+            });
+        }
+        group.finish();
+    });
+}
+
+fn bench_span_fields(c: &mut Criterion) {
+    let mut cfg = Config::prod();
+    cfg.file.dir = env::temp_dir()
+        .join("common-tracing-bench")
+        .to_string_lossy()
+        .to_string();
+    bench_with(c, "bunyan", &cfg);
+
+    // `seq` is appended without parsing the records.
+    cfg.seq_field = true;
+    bench_with(c, "bunyan_seq", &cfg);
+
+    // Records are re-parsed to rename the message field.
+    cfg.file.message_field = "message".to_string();
+    bench_with(c, "bunyan_rewritten", &cfg);
+}
+
+criterion_group!(benches, bench_span_fields);
+criterion_main!(benches);
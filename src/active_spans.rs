@@ -0,0 +1,136 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+static ACTIVE_SPANS: Lazy<Mutex<HashMap<u64, ActiveSpan>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct ActiveSpan {
+    name: &'static str,
+    target: &'static str,
+    parent: Option<u64>,
+    fields: Vec<(String, String)>,
+    created: Instant,
+}
+
+/// A span open when `dump_active_spans` is called.
+#[derive(Clone, Debug)]
+pub struct ActiveSpanInfo {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub target: &'static str,
+    /// Fields formatted with `Debug`.
+    pub fields: Vec<(String, String)>,
+    /// Time since the span was created.
+    pub age: Duration,
+}
+
+/// Returns the spans not closed yet, the oldest first, e.g. to find the
+/// requests stuck in a deadlock from an admin endpoint or a signal handler.
+///
+/// Empty unless `Config::track_active_spans` is enabled.
+pub fn dump_active_spans() -> Vec<ActiveSpanInfo> {
+    let spans = ACTIVE_SPANS.lock().unwrap();
+    let mut infos = spans
+        .iter()
+        .map(|(id, span)| ActiveSpanInfo {
+            id: *id,
+            parent: span.parent,
+            name: span.name,
+            target: span.target,
+            fields: span.fields.clone(),
+            age: span.created.elapsed(),
+        })
+        .collect::<Vec<_>>();
+    infos.sort_by_key(|info| Reverse(info.age));
+    infos
+}
+
+/// A layer keeps track of the open spans for `dump_active_spans`.
+///
+/// Every span creation and close takes a global lock, enable it only
+/// when the diagnosis is worth the overhead.
+pub struct ActiveSpansLayer;
+
+impl<S> Layer<S> for ActiveSpansLayer
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let meta = attrs.metadata();
+        let mut visitor = FieldsVisitor::default();
+        attrs.record(&mut visitor);
+
+        let parent = ctx
+            .span(id)
+            .and_then(|span| span.parent())
+            .map(|parent| parent.id().into_u64());
+
+        let span = ActiveSpan {
+            name: meta.name(),
+            target: meta.target(),
+            parent,
+            fields: visitor.fields,
+            created: Instant::now(),
+        };
+        ACTIVE_SPANS.lock().unwrap().insert(id.into_u64(), span);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = FieldsVisitor::default();
+        values.record(&mut visitor);
+
+        if let Some(span) = ACTIVE_SPANS.lock().unwrap().get_mut(&id.into_u64()) {
+            span.fields.extend(visitor.fields);
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        ACTIVE_SPANS.lock().unwrap().remove(&id.into_u64());
+    }
+}
+
+#[derive(Default)]
+struct FieldsVisitor {
+    fields: Vec<(String, String)>,
+}
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields
+            .push((field.name().to_string(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.fields
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
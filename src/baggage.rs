@@ -0,0 +1,57 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::Context;
+use opentelemetry::ContextGuard;
+use opentelemetry::KeyValue;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Set a baggage value, e.g. `tenant`, in the current OpenTelemetry context
+/// until the returned guard is dropped.
+///
+/// The baggage is propagated to the downstream services by
+/// `inject_span_to_tonic_request`.
+pub fn set_baggage(key: &str, value: &str) -> ContextGuard {
+    Context::current_with_baggage(vec![KeyValue::new(key.to_string(), value.to_string())]).attach()
+}
+
+/// Read a baggage value set by `set_baggage`, or received from the upstream
+/// service by `extract_remote_span_as_parent`.
+pub fn baggage_value(key: &str) -> Option<String> {
+    // Only a `&'static str` converts into a `Key` without copying.
+    let key = key.to_string();
+    if let Some(value) = Context::current().baggage().get(key.clone()) {
+        return Some(value.to_string());
+    }
+    let cx = tracing::Span::current().context();
+    let value = cx.baggage().get(key)?;
+    Some(value.to_string())
+}
+
+/// The context of the current span, with the baggage set by `set_baggage` merged in.
+pub(crate) fn current_context() -> Context {
+    let cx = tracing::Span::current().context();
+    let current = Context::current();
+    let baggage = current.baggage();
+    if baggage.is_empty() {
+        return cx;
+    }
+    cx.with_baggage(
+        baggage
+            .iter()
+            .map(|(k, (v, _))| KeyValue::new(k.clone(), v.clone()))
+            .collect::<Vec<_>>(),
+    )
+}
@@ -0,0 +1,56 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+
+use tracing::subscriber::DefaultGuard;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::EnvFilter;
+
+use crate::init_logging;
+use crate::pipe_writer::PipeTolerant;
+use crate::Config;
+
+/// Install a minimal stderr logger for the startup, before the config is loaded.
+///
+/// Events are written synchronously, filtered by `RUST_LOG`, default to `INFO`.
+/// The logger is only the default of the current thread, it is removed when the
+/// returned guard is dropped, or replaced by `BootstrapGuard::handoff`.
+pub fn init_bootstrap_logger() -> BootstrapGuard {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("INFO"));
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(PipeTolerant::new(io::stderr))
+        .with_env_filter(env_filter)
+        .finish();
+
+    BootstrapGuard {
+        _default: tracing::subscriber::set_default(subscriber),
+    }
+}
+
+/// Keeps the bootstrap logger installed, see `init_bootstrap_logger`.
+pub struct BootstrapGuard {
+    _default: DefaultGuard,
+}
+
+impl BootstrapGuard {
+    /// Removes the bootstrap logger and installs the full one with `init_logging`.
+    ///
+    /// The bootstrap events are already on stderr at this point,
+    /// thus they always precede the events of the full logger.
+    pub fn handoff(self, name: &str, cfg: &Config) -> Vec<WorkerGuard> {
+        drop(self);
+        init_logging(name, cfg)
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Max number of events a slow consumer can lag behind before it misses events.
+const BROADCAST_CAPACITY: usize = 1024;
+
+static BROADCAST: Lazy<broadcast::Sender<Arc<BroadcastEvent>>> =
+    Lazy::new(|| broadcast::channel(BROADCAST_CAPACITY).0);
+
+/// An event forwarded to the broadcast consumers.
+#[derive(Clone, Debug)]
+pub struct BroadcastEvent {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /// Other fields than `message`, formatted with `Debug`.
+    pub fields: Vec<(String, String)>,
+}
+
+/// Subscribe to the events forwarded by `BroadcastLayer`, e.g. to stream logs
+/// live to a plugin UI. Dropping the receiver unsubscribes.
+///
+/// A consumer never blocks logging: if it lags more than 1024 events behind,
+/// the oldest ones are skipped and `recv` reports `RecvError::Lagged`.
+pub fn subscribe_events() -> broadcast::Receiver<Arc<BroadcastEvent>> {
+    BROADCAST.subscribe()
+}
+
+/// A layer forwards events to the consumers of `subscribe_events`,
+/// without interfering with the other layers.
+pub struct BroadcastLayer;
+
+impl<S: Subscriber> Layer<S> for BroadcastLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // Nobody is listening, save the formatting.
+        if BROADCAST.receiver_count() == 0 {
+            return;
+        }
+
+        let meta = event.metadata();
+        let mut visitor = BroadcastVisitor::default();
+        event.record(&mut visitor);
+
+        let event = BroadcastEvent {
+            time: SystemTime::now(),
+            level: *meta.level(),
+            target: meta.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        };
+
+        // It fails only if all receivers were dropped meanwhile.
+        let _ = BROADCAST.send(Arc::new(event));
+    }
+}
+
+#[derive(Default)]
+struct BroadcastVisitor {
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Visit for BroadcastVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .push((field.name().to_string(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::Level;
+use tracing::Metadata;
+use tracing_appender::non_blocking::NonBlocking;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::disk_full::DiskFullWriter;
+use crate::file_appender::FileAppender;
+
+/// Bytes sent to the non-blocking writers but not yet written by their workers.
+static BUFFERED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Lines dropped because the buffered bytes exceeded the bound.
+static DROPPED_LINES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the bytes buffered in memory by all the non-blocking file writers.
+pub fn buffered_log_bytes() -> u64 {
+    BUFFERED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Returns the number of lines dropped because `max_buffered_bytes` was exceeded.
+pub fn dropped_log_lines() -> u64 {
+    DROPPED_LINES.load(Ordering::Relaxed)
+}
+
+/// Wraps the writer of a non-blocking worker, releases the bytes it writes.
+pub struct ReleaseOnWrite<W>(pub W);
+
+impl<W: Write> Write for ReleaseOnWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        release(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Wraps a non-blocking writer, switching to lossy mode when the bytes
+/// buffered by all writers exceed `max_bytes`: new lines are dropped until the
+/// workers catch up. `max_bytes = 0` means unbounded.
+///
+/// The workers must write through `ReleaseOnWrite` for the accounting to work.
+///
+/// With `with_sync_errors`, `ERROR` lines bypass the buffer: they are written
+/// and flushed to the file by the caller, and may thus precede lines of
+/// earlier events still buffered.
+#[derive(Clone)]
+pub struct BoundedWriter {
+    inner: NonBlocking,
+    /// The appender the worker of `inner` writes to.
+    appender: SharedAppender,
+    max_bytes: u64,
+    /// Write the `ERROR` lines to `appender` synchronously.
+    sync: bool,
+    /// The line being written is an `ERROR` one.
+    sync_line: bool,
+    /// Look for `ERROR` in the Bunyan records, they come without metadata.
+    sniff_level: bool,
+}
+
+impl BoundedWriter {
+    pub fn new(inner: NonBlocking, appender: SharedAppender, max_bytes: u64) -> Self {
+        Self {
+            inner,
+            appender,
+            max_bytes,
+            sync: false,
+            sync_line: false,
+            sniff_level: false,
+        }
+    }
+
+    /// Write `ERROR` lines synchronously to the appender.
+    pub fn with_sync_errors(mut self) -> Self {
+        self.sync = true;
+        self
+    }
+
+    /// Replace the appender with `next` once the worker has written the lines
+    /// buffered so far, which are thus written to the previous appender before
+    /// it is flushed and dropped.
+    ///
+    /// Waits at most `timeout` for the worker: the lines it has not written by
+    /// then go to `next`, none is lost. Returns whether all of them reached
+    /// the previous appender.
+    pub fn swap_appender(
+        &self,
+        next: DiskFullWriter<FileAppender>,
+        timeout: Duration,
+    ) -> io::Result<bool> {
+        let (done, drained) = mpsc::channel();
+        self.appender.0.lock().unwrap().next = Some((next, done));
+
+        // The marker follows the buffered lines through the channel of the
+        // worker, which swaps the appender when it reaches it.
+        let len = SWAP_MARKER.len() as u64;
+        BUFFERED_BYTES.fetch_add(len, Ordering::Relaxed);
+        let mut inner = self.inner.clone();
+        let dropped = inner.error_counter().dropped_lines();
+        inner.write_all(SWAP_MARKER)?;
+        if inner.error_counter().dropped_lines() > dropped {
+            release(len);
+        }
+
+        let drained = drained.recv_timeout(timeout).is_ok();
+        if !drained {
+            // The worker is late, or a lossy channel dropped the marker.
+            self.appender.0.lock().unwrap().swap();
+        }
+        Ok(drained)
+    }
+}
+
+/// Written to the worker by `swap_appender`, never to the files.
+const SWAP_MARKER: &[u8] = b"\0common_tracing::swap_appender\0";
+
+/// Bunyan level of `ERROR` records, core fields are never escaped.
+const BUNYAN_ERROR_LEVEL: &[u8] = b"\"level\":50";
+
+impl Write for BoundedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.sync {
+            let sniffed = self.sniff_level
+                && buf
+                    .windows(BUNYAN_ERROR_LEVEL.len())
+                    .any(|w| w == BUNYAN_ERROR_LEVEL);
+            if self.sync_line || sniffed {
+                self.appender.write_all(buf)?;
+                self.appender.flush()?;
+                return Ok(buf.len());
+            }
+        }
+
+        let len = buf.len() as u64;
+        let buffered = BUFFERED_BYTES.fetch_add(len, Ordering::Relaxed) + len;
+        if self.max_bytes > 0 && buffered > self.max_bytes {
+            release(len);
+            DROPPED_LINES.fetch_add(1, Ordering::Relaxed);
+            return Ok(buf.len());
+        }
+
+        // A lossy non-blocking writer drops lines silently if its channel is full,
+        // those bytes never reach the worker. It's approximate with concurrent writers.
+        let dropped = self.inner.error_counter().dropped_lines();
+        let n = self.inner.write(buf)?;
+        if self.inner.error_counter().dropped_lines() > dropped {
+            release(len);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for BoundedWriter {
+    type Writer = BoundedWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        let mut writer = self.clone();
+        writer.sniff_level = self.sync;
+        writer
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let mut writer = self.clone();
+        writer.sync_line = self.sync && *meta.level() == Level::ERROR;
+        writer
+    }
+}
+
+/// A rolling file appender shared by a non-blocking worker and the
+/// synchronous writes of `BoundedWriter`.
+///
+/// The synchronous writes block the caller too with `DiskFullPolicy::Block`.
+#[derive(Clone)]
+pub struct SharedAppender(Arc<Mutex<Slot>>);
+
+struct Slot {
+    appender: DiskFullWriter<FileAppender>,
+    /// The appender set by `swap_appender`, notified once swapped.
+    next: Option<(DiskFullWriter<FileAppender>, mpsc::Sender<()>)>,
+}
+
+impl Slot {
+    fn swap(&mut self) {
+        if let Some((next, done)) = self.next.take() {
+            let _ = self.appender.flush();
+            self.appender = next;
+            let _ = done.send(());
+        }
+    }
+}
+
+impl SharedAppender {
+    pub fn new(appender: DiskFullWriter<FileAppender>) -> Self {
+        Self(Arc::new(Mutex::new(Slot {
+            appender,
+            next: None,
+        })))
+    }
+}
+
+impl Write for SharedAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut slot = self.0.lock().unwrap();
+        if buf == SWAP_MARKER {
+            slot.swap();
+            return Ok(buf.len());
+        }
+        slot.appender.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().appender.flush()
+    }
+}
+
+fn release(n: u64) {
+    let _ = BUFFERED_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_sub(n))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Barrier;
+    use std::thread;
+
+    use tracing_appender::non_blocking::NonBlockingBuilder;
+    use tracing_appender::rolling;
+
+    use super::*;
+    use crate::DiskFullPolicy;
+
+    const THREADS: usize = 4;
+    const LINES: usize = 500;
+
+    fn new_appender(dir: &Path) -> DiskFullWriter<FileAppender> {
+        let appender = FileAppender::Utc(rolling::never(dir, "swap"));
+        DiskFullWriter::new(
+            appender,
+            dir.to_str().unwrap(),
+            "swap",
+            DiskFullPolicy::default(),
+        )
+    }
+
+    fn read_lines(dir: &Path) -> Vec<String> {
+        let content = fs::read_to_string(dir.join("swap")).unwrap_or_default();
+        content.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_swap_appender_loses_no_line() {
+        let (old_dir, new_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let appender = SharedAppender::new(new_appender(old_dir.path()));
+        let (inner, guard) = NonBlockingBuilder::default()
+            .lossy(false)
+            .finish(ReleaseOnWrite(appender.clone()));
+        let writer = BoundedWriter::new(inner, appender, 0);
+
+        // The threads write half of their lines before the swap, the rest during it.
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let threads = (0..THREADS)
+            .map(|t| {
+                let mut writer = writer.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    for i in 0..LINES {
+                        if i == LINES / 2 {
+                            barrier.wait();
+                        }
+                        writer.write_all(format!("{}-{}\n", t, i).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        barrier.wait();
+        let drained = writer
+            .swap_appender(new_appender(new_dir.path()), Duration::from_secs(10))
+            .unwrap();
+        assert!(drained);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        drop(writer);
+        // Waits for the worker to write the remaining lines.
+        drop(guard);
+
+        let old_lines = read_lines(old_dir.path());
+        let new_lines = read_lines(new_dir.path());
+        let all = old_lines.iter().chain(&new_lines).collect::<HashSet<_>>();
+        assert_eq!(old_lines.len() + new_lines.len(), THREADS * LINES);
+        assert_eq!(all.len(), THREADS * LINES);
+        // The lines buffered before the swap went to the previous appender.
+        let old_lines = old_lines.into_iter().collect::<HashSet<_>>();
+        for t in 0..THREADS {
+            for i in 0..LINES / 2 {
+                assert!(old_lines.contains(&format!("{}-{}", t, i)));
+            }
+        }
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::event_time::event_time;
+use crate::event_time::rfc3339;
+use crate::limited_fields::FIELDS_TRUNCATED;
+use crate::source_location;
+use crate::span_path;
+use crate::span_path::SPAN_PATH_FIELD;
+use crate::SourceLocation;
+
+/// The key Bunyan uses for the primary text of a record.
+pub const BUNYAN_MESSAGE_FIELD: &str = "msg";
+
+/// The fields Bunyan writes in every record.
+const BUNYAN_CORE_FIELDS: [&str; 10] = [
+    "v", "name", "msg", "level", "hostname", "pid", "time", "target", "line", "file",
+];
+
+/// Wraps the writer of `BunyanFormattingLayer` and post-processes its newline
+/// delimited JSON records, for the options the formatter has no hook for: it
+/// only writes static default fields, and always writes the message under `msg`.
+///
+/// Members are appended without parsing the record, they are written just
+/// before its closing brace:
+///
+/// - With `with_seq`, a `seq` field numbering the records.
+/// - With `with_span_path`, a `span_path` member, kept by a `CaptureSpanPath`
+///   stacked before the Bunyan layer.
+/// - With `with_uptime`, an `uptime_ms` field with the milliseconds since the
+///   writer was created.
+///
+/// The other options change the members written by Bunyan, the record is
+/// re-parsed then, which costs an extra JSON round trip per line:
+///
+/// - `new` renames the message field. The output is no longer valid Bunyan
+///   format then, e.g. the `bunyan` CLI will not recognize the records any more.
+/// - With `with_name_from`, the Bunyan `name` is taken from a field.
+/// - With `with_max_fields`, the other fields of a record are capped.
+/// - The `time` of the records emitted in `with_event_time` is replaced.
+/// - With `with_source_location`, the location members are added or removed,
+///   `CaptureLocation` must be stacked before the Bunyan layer to add them.
+///
+/// Without any option, the records are passed through untouched.
+pub struct BunyanPostProcessor<W> {
+    inner: W,
+    to: String,
+    max_fields: Option<usize>,
+    seq: Option<AtomicU64>,
+    started: Option<Instant>,
+    name_from: String,
+    location: SourceLocation,
+    span_path: bool,
+}
+
+impl<W> BunyanPostProcessor<W> {
+    pub fn new(inner: W, to: impl Into<String>) -> Self {
+        Self {
+            inner,
+            to: to.into(),
+            max_fields: None,
+            seq: None,
+            started: None,
+            name_from: "".to_string(),
+            location: SourceLocation::Auto,
+            span_path: false,
+        }
+    }
+
+    /// Keep at most `max` fields besides the Bunyan core fields, in record order,
+    /// followed by `fields_truncated` if some were dropped. The default fields,
+    /// e.g. `app_version`, and the span fields count too. `None` keeps all fields.
+    pub fn with_max_fields(mut self, max: Option<usize>) -> Self {
+        self.max_fields = max;
+        self
+    }
+
+    /// Append a `seq` field to the records if `on`, starting at 0.
+    pub fn with_seq(mut self, on: bool) -> Self {
+        self.seq = if on { Some(AtomicU64::new(0)) } else { None };
+        self
+    }
+
+    /// Append an `uptime_ms` field to the records if `on`, counting from now.
+    pub fn with_uptime(mut self, on: bool) -> Self {
+        self.started = if on { Some(Instant::now()) } else { None };
+        self
+    }
+
+    /// Add or remove the `file`, `line` and `module_path` of the records.
+    pub fn with_source_location(mut self, location: SourceLocation) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Add the `span_path` of the records if `on`.
+    pub fn with_span_path(mut self, on: bool) -> Self {
+        self.span_path = on;
+        self
+    }
+
+    /// Replace the Bunyan `name` of a record with the string value of `field`,
+    /// if the record has it, e.g. a `tenant` field of the current span.
+    /// Empty `field` keeps the name.
+    pub fn with_name_from(mut self, field: impl Into<String>) -> Self {
+        self.name_from = field.into();
+        self
+    }
+}
+
+impl<'a, W> MakeWriter<'a> for BunyanPostProcessor<W>
+where W: MakeWriter<'a>
+{
+    type Writer = BunyanPostWriter<'a, W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        BunyanPostWriter {
+            inner: self.inner.make_writer(),
+            to: &self.to,
+            max_fields: self.max_fields,
+            seq: self.seq.as_ref(),
+            started: self.started,
+            name_from: &self.name_from,
+            location: self.location,
+            span_path: self.span_path,
+            buf: vec![],
+        }
+    }
+}
+
+pub struct BunyanPostWriter<'a, W: Write> {
+    inner: W,
+    to: &'a str,
+    max_fields: Option<usize>,
+    seq: Option<&'a AtomicU64>,
+    started: Option<Instant>,
+    name_from: &'a str,
+    location: SourceLocation,
+    span_path: bool,
+    buf: Vec<u8>,
+}
+
+impl<'a, W: Write> BunyanPostWriter<'a, W> {
+    /// Whether the members written by Bunyan are changed, which requires parsing the records.
+    fn rewrites(&self) -> bool {
+        self.to != BUNYAN_MESSAGE_FIELD
+            || self.max_fields.is_some()
+            || !self.name_from.is_empty()
+            || self.location != SourceLocation::Auto
+            || event_time().is_some()
+    }
+
+    /// Whether members are appended to the records.
+    fn appends(&self) -> bool {
+        self.seq.is_some() || self.started.is_some() || self.span_path
+    }
+
+    /// Write the appended members before the closing brace of `line`.
+    fn append_members(&mut self, line: &[u8]) -> io::Result<()> {
+        let record = line.strip_suffix(b"\n").unwrap_or(line);
+        let record = match record.strip_suffix(b"}") {
+            Some(record) if record.starts_with(b"{") => record,
+            // Not a JSON record, pass it through untouched.
+            _ => return self.inner.write_all(line),
+        };
+
+        let mut out = Vec::with_capacity(line.len() + 64);
+        out.extend_from_slice(record);
+        if let Some(seq) = self.seq {
+            write!(out, ",\"seq\":{}", seq.fetch_add(1, Ordering::Relaxed))?;
+        }
+        if let Some(path) = self.span_path.then(span_path::current).flatten() {
+            write!(out, ",\"{}\":", SPAN_PATH_FIELD)?;
+            serde_json::to_writer(&mut out, &path)?;
+        }
+        if let Some(started) = self.started {
+            write!(out, ",\"uptime_ms\":{}", started.elapsed().as_millis() as u64)?;
+        }
+        out.extend_from_slice(b"}\n");
+        self.inner.write_all(&out)
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if !self.rewrites() {
+            return self.append_members(line);
+        }
+
+        let record = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(line);
+        match record {
+            Ok(record) => {
+                // Rebuild the record instead of remove/insert to keep the field order.
+                let mut others = 0;
+                let mut truncated = false;
+                let mut record = record
+                    .into_iter()
+                    .filter(|(k, _)| {
+                        let max = match self.max_fields {
+                            Some(max) if !BUNYAN_CORE_FIELDS.contains(&k.as_str()) => max,
+                            _ => return true,
+                        };
+                        others += 1;
+                        truncated |= others > max;
+                        others <= max
+                    })
+                    .map(|(k, v)| match k.as_str() {
+                        BUNYAN_MESSAGE_FIELD => (self.to.to_string(), v),
+                        _ => (k, v),
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                if truncated {
+                    record.insert(FIELDS_TRUNCATED.to_string(), serde_json::Value::from(true));
+                }
+                if !self.name_from.is_empty() {
+                    let name = record.get(self.name_from).and_then(|v| v.as_str());
+                    if let Some(name) = name.map(|name| name.to_string()) {
+                        record.insert("name".to_string(), serde_json::Value::from(name));
+                    }
+                }
+                if let Some(seq) = self.seq {
+                    let seq = seq.fetch_add(1, Ordering::Relaxed);
+                    record.insert("seq".to_string(), serde_json::Value::from(seq));
+                }
+                source_location::apply(&mut record, self.location, &source_location::current());
+                if let Some(path) = self.span_path.then(span_path::current).flatten() {
+                    record.insert(SPAN_PATH_FIELD.to_string(), serde_json::Value::from(path));
+                }
+                // Written by Bunyan with the current time.
+                if let Some(time) = event_time() {
+                    record.insert("time".to_string(), serde_json::Value::from(rfc3339(time)));
+                }
+                if let Some(started) = self.started {
+                    let uptime = started.elapsed().as_millis() as u64;
+                    record.insert("uptime_ms".to_string(), serde_json::Value::from(uptime));
+                }
+                let mut out = Vec::with_capacity(line.len() + 32);
+                serde_json::to_writer(&mut out, &record)?;
+                out.push(b'\n');
+                self.inner.write_all(&out)
+            }
+            // Not a JSON record, pass it through untouched.
+            Err(_) => self.inner.write_all(line),
+        }
+    }
+}
+
+impl<'a, W: Write> Write for BunyanPostWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.rewrites() && !self.appends() {
+            return self.inner.write(buf);
+        }
+
+        // Formatters write a whole record at once, it is parsed in place then,
+        // only partial lines are copied.
+        let mut start = 0;
+        while let Some(pos) = buf[start..].iter().position(|b| *b == b'\n') {
+            let end = start + pos + 1;
+            if self.buf.is_empty() {
+                self.write_line(&buf[start..end])?;
+            } else {
+                self.buf.extend_from_slice(&buf[start..end]);
+                let line = std::mem::take(&mut self.buf);
+                self.write_line(&line)?;
+            }
+            start = end;
+        }
+        self.buf.extend_from_slice(&buf[start..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            self.write_line(&line)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<'a, W: Write> Drop for BunyanPostWriter<'a, W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn post_process(
+        new: impl FnOnce(Box<dyn Fn() -> Buffer>) -> BunyanPostProcessor<Box<dyn Fn() -> Buffer>>,
+        writes: &[&str],
+    ) -> Vec<String> {
+        let out = Buffer::default();
+        let writer = out.clone();
+        let post = new(Box::new(move || writer.clone()));
+        let mut w = post.make_writer();
+        for buf in writes {
+            w.write_all(buf.as_bytes()).unwrap();
+        }
+        drop(w);
+        let out = out.0.lock().unwrap();
+        String::from_utf8(out.clone()).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_appended_members_keep_the_record() {
+        let record = r#"{"v":0,"msg":"hi","z":1,"a":2}"#;
+        let lines = post_process(|w| BunyanPostProcessor::new(w, "msg").with_seq(true), &[
+            &format!("{}\n{}\nnot json\n", record, record),
+        ]);
+        assert_eq!(lines, [
+            r#"{"v":0,"msg":"hi","z":1,"a":2,"seq":0}"#,
+            r#"{"v":0,"msg":"hi","z":1,"a":2,"seq":1}"#,
+            "not json",
+        ]);
+    }
+
+    #[test]
+    fn test_rewritten_members() {
+        let lines = post_process(
+            |w| BunyanPostProcessor::new(w, "message").with_seq(true),
+            &[r#"{"v":0,"msg":"hi"}"#, "\n"],
+        );
+        assert_eq!(lines, [r#"{"v":0,"message":"hi","seq":0}"#]);
+    }
+}
@@ -12,14 +12,364 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::env;
+use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use opentelemetry::sdk::trace::ShouldSample;
+use opentelemetry::sdk::InstrumentationLibrary;
+use opentelemetry::trace::Link;
+use opentelemetry::trace::OrderMap;
+use opentelemetry::trace::SamplingResult;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::trace::TraceId;
+use opentelemetry::Context;
+use opentelemetry::Key;
+use opentelemetry::Value;
+use serde::ser::SerializeSeq;
+use serde::Serializer;
+use tracing::field::Field;
+use tracing::level_filters::LevelFilter;
+use tracing::Level;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::time::SystemTime;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+
+use crate::event_time::EventTimeOr;
 
 /// Config for tracing.
-#[derive(Clone, Debug, PartialEq, Eq, Default, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
 pub struct Config {
     pub file: FileConfig,
     pub stderr: StderrConfig,
+    pub otlp_logs: OtlpLogsConfig,
+    pub webhook: WebhookConfig,
+    pub sentry: SentryConfig,
+    pub trace: TraceConfig,
+    /// Version of the host application, emitted as `app_version` on every
+    /// file log record. Usually set to the host's `CARGO_PKG_VERSION`.
+    /// Empty means the field is omitted.
+    pub app_version: String,
+    /// Forward records of the `log` crate into tracing.
+    ///
+    /// Disable it if the host application installs its own `log` logger.
+    pub capture_log_crate: bool,
+    /// Max level of the captured `log` records, e.g. `WARN`.
+    /// Empty or invalid means capturing all records.
+    pub log_crate_level: String,
+    /// The outputs of every level, e.g. `TRACE` to the file only, `INFO` to
+    /// stderr too and `ERROR` to sentry and the webhook too, see `LevelRoute`.
+    /// Empty means each output uses its own level option, e.g. `file.level`.
+    pub level_routes: Vec<LevelRoute>,
+    /// `(target, level)` pairs downgrading the level of the events
+    /// emitted by a target and its sub modules, e.g. `("hyper", Level::WARN)`.
+    ///
+    /// See `TargetLevelOverrides` for how the events are re-emitted.
+    #[serde(serialize_with = "serialize_level_overrides")]
+    pub target_level_overrides: Vec<(String, Level)>,
+    /// Name of the span field raising the file and stderr levels for the
+    /// events inside the span, e.g. `log_level = "debug"`.
+    /// Empty disables it.
+    pub span_level_field: String,
+    pub event_sampling: EventSamplingConfig,
+    pub broadcast: BroadcastConfig,
+    pub error_buffer: ErrorBufferConfig,
+    /// Constant `(key, value)` fields added to every log line, e.g. `("cluster", "c1")`.
+    ///
+    /// They are added to the file, stderr and OTLP log records, and to the
+    /// resource of the exported traces.
+    pub global_fields: Vec<(String, String)>,
+    /// Timer of the text and logfmt lines written to files and stderr,
+    /// e.g. a `FixedTime` to assert exact lines in tests.
+    /// `None` means the system clock in RFC3339 UTC.
+    ///
+    /// Bunyan JSON records always use the system clock.
+    #[serde(skip)]
+    pub timer: Option<CustomTimer>,
+    /// Fields read on every event from the context of the emitting task or
+    /// thread, e.g. request metadata kept in a `tokio::task_local`, added to
+    /// the text, logfmt and stderr JSON lines. Bunyan JSON file records do not have them.
+    #[serde(skip)]
+    pub context_fields: Option<ContextFields>,
+    /// A span opened by `init_logging` reported as the current span of the
+    /// events emitted outside any span, e.g. with `service` and `node` fields,
+    /// in all outputs and in the traces. `None` leaves such events without span.
+    #[serde(skip)]
+    pub default_span: Option<DefaultSpan>,
+    /// Keep track of the open spans, listed by `dump_active_spans`.
+    pub track_active_spans: bool,
+    /// Log the busy and idle time of every span on close, see `SpanTimingLayer`.
+    pub span_timing: bool,
+    /// Add a `seq` field numbering the lines of every file and stderr output,
+    /// starting at 0. Numbers are taken before the lines are buffered, thus a gap
+    /// downstream means lines were dropped, see `dropped_log_lines`.
+    pub seq_field: bool,
+    /// Add an `uptime_ms` field with the milliseconds since `init_logging` to the
+    /// lines of every file and stderr output, e.g. to order a cold start.
+    pub uptime_field: bool,
+    /// Max number of fields of an event written to files and stderr, besides the
+    /// message, e.g. to protect parsers limiting the field count. The others are
+    /// dropped and `fields_truncated=true` is added. `None` keeps all fields.
+    ///
+    /// Bunyan JSON records count all fields but the core ones, including the
+    /// span and global fields. Text lines cap the span fields too.
+    pub max_fields_per_event: Option<usize>,
+    /// Renders the event and span fields of the text lines written to files
+    /// and stderr, e.g. to escape or redact values. `None` renders them as
+    /// `tracing_subscriber` does. Fields are still capped by `max_fields_per_event`.
+    #[serde(skip)]
+    pub field_formatter: Option<FieldFormatter>,
+    /// Names of the spans hidden from all outputs, together with the spans
+    /// and events inside them, e.g. `health_check`.
+    pub exclude_span_names: Vec<String>,
+    /// Targets hidden from all outputs, e.g. `hyper` also hides `hyper::proto`.
+    pub exclude_targets: Vec<String>,
+    /// How levels are written in the text and logfmt lines of files and stderr.
+    pub level_style: LevelStyle,
+    /// Separator of the pairs of the logfmt lines of files and stderr, a space
+    /// by default, e.g. `'\t'` for parsers splitting on tabs. Values containing
+    /// it are escaped, see `Logfmt::with_delimiter`.
+    pub logfmt_delimiter: char,
+    /// Whether the JSON lines of files and stderr have the `file`, `line` and
+    /// `module_path` of the event. `Auto` keeps the default of each format:
+    /// Bunyan records have `file` and `line` if known, stderr JSON lines none.
+    pub json_source_location: SourceLocation,
+    /// Add a `span_path` member with the names of the spans of the event from
+    /// the root, joined with `:`, e.g. `server:handle_request:query`, to the
+    /// JSON lines of files and stderr. Lines outside of any span have none.
+    pub span_path_field: bool,
+    /// Panic in `init_logging` on an invalid level option or `RUST_LOG`
+    /// directive, e.g. `mymod=dbug`, see `Config::validate_filters`. Otherwise
+    /// the first one is reported in `InitOutcome::warnings` and it is ignored.
+    pub strict_filter: bool,
+    /// End of the text and logfmt lines of files and stderr, e.g. `Crlf` for
+    /// Windows tools. JSON lines always end with `\n`, as NDJSON, and so do the
+    /// query log lines.
+    pub line_ending: LineEnding,
+    /// Layers added by the user with `Config::with_extra_layer`.
+    #[serde(skip)]
+    pub extra_layers: ExtraLayers,
+}
+
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The user supplied layers, taken by the first `init_logging` using the config.
+///
+/// Clones of a config share the same layers.
+#[derive(Clone, Default)]
+pub struct ExtraLayers(Arc<Mutex<Vec<BoxedLayer>>>);
+
+impl ExtraLayers {
+    pub(crate) fn take(&self) -> Vec<BoxedLayer> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Debug for ExtraLayers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ExtraLayers({})", self.0.lock().unwrap().len())
+    }
+}
+
+impl PartialEq for ExtraLayers {
+    /// Layers can not be compared: equal if they are the same layers, or both empty.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+            || (self.0.lock().unwrap().is_empty() && other.0.lock().unwrap().is_empty())
+    }
+}
+
+impl Eq for ExtraLayers {}
+
+/// A user supplied timer for the formatted lines.
+#[derive(Clone)]
+pub struct CustomTimer(pub Arc<dyn FormatTime + Send + Sync>);
+
+impl CustomTimer {
+    pub fn new(timer: impl FormatTime + Send + Sync + 'static) -> Self {
+        Self(Arc::new(timer))
+    }
+}
+
+impl FormatTime for CustomTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        self.0.format_time(w)
+    }
+}
+
+impl Debug for CustomTimer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomTimer")
+    }
+}
+
+impl PartialEq for CustomTimer {
+    /// Timers can not be compared: equal if they are the same timer.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CustomTimer {}
+
+/// A user supplied accessor of the context fields of the current task, e.g.
+///
+/// `ContextFields::new(|| REQUEST.try_with(|r| vec![("tenant".to_string(), r.tenant.clone())]).unwrap_or_default())`
+///
+/// It is called on the thread emitting the event, for every formatted line:
+/// keep it cheap, and do not log from it.
+#[derive(Clone)]
+pub struct ContextFields(pub Arc<dyn Fn() -> Vec<(String, String)> + Send + Sync>);
+
+impl ContextFields {
+    pub fn new(accessor: impl Fn() -> Vec<(String, String)> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(accessor))
+    }
+}
+
+impl Debug for ContextFields {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ContextFields")
+    }
+}
+
+impl PartialEq for ContextFields {
+    /// Accessors can not be compared: equal if they are the same accessor.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ContextFields {}
+
+/// A user supplied constructor of the default span, e.g.
+///
+/// `DefaultSpan::new(|| tracing::info_span!("root", service = "query", node = %node_id))`
+///
+/// It is called once by `init_logging`, after the subscriber is installed.
+/// The span is closed, and exported, by `shutdown_logging`; the events emitted
+/// afterwards have no span.
+///
+/// The spans opened outside any span are still roots, not children of the
+/// default span. An exporter keeps a bounded number of events per span: the
+/// default span of a long running process has only the first of its events.
+#[derive(Clone)]
+pub struct DefaultSpan(pub Arc<dyn Fn() -> tracing::Span + Send + Sync>);
+
+impl DefaultSpan {
+    pub fn new(constructor: impl Fn() -> tracing::Span + Send + Sync + 'static) -> Self {
+        Self(Arc::new(constructor))
+    }
+}
+
+impl Debug for DefaultSpan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DefaultSpan")
+    }
+}
+
+impl PartialEq for DefaultSpan {
+    /// Constructors can not be compared: equal if they are the same constructor.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for DefaultSpan {}
+
+/// A user supplied renderer of one field of the text lines, writing the
+/// key and value of the field, e.g. to redact passwords:
+///
+/// `FieldFormatter::new(|w, field, value| match field.name() { "password" => write!(w, "password=***"), "message" => write!(w, "{:?}", value), name => write!(w, "{}={:?}", name, value) })`
+///
+/// Fields are separated by a space, a field rendered as nothing is skipped.
+/// The message is passed as the `message` field, `log.*` fields never are.
+#[derive(Clone)]
+pub struct FieldFormatter(pub Arc<FieldFormatFn>);
+
+pub type FieldFormatFn =
+    dyn Fn(&mut Writer<'_>, &Field, &dyn Debug) -> std::fmt::Result + Send + Sync;
+
+impl FieldFormatter {
+    pub fn new(
+        f: impl Fn(&mut Writer<'_>, &Field, &dyn Debug) -> std::fmt::Result + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl Debug for FieldFormatter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FieldFormatter")
+    }
+}
+
+impl PartialEq for FieldFormatter {
+    /// Formatters can not be compared: equal if they are the same formatter.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for FieldFormatter {}
+
+fn serialize_level_overrides<S: Serializer>(
+    overrides: &[(String, Level)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(overrides.len()))?;
+    for (target, level) in overrides {
+        seq.serialize_element(&(target, level.as_str()))?;
+    }
+    seq.end()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: FileConfig::default(),
+            stderr: StderrConfig::default(),
+            otlp_logs: OtlpLogsConfig::default(),
+            webhook: WebhookConfig::default(),
+            sentry: SentryConfig::default(),
+            trace: TraceConfig::default(),
+            app_version: "".to_string(),
+            capture_log_crate: true,
+            log_crate_level: "".to_string(),
+            level_routes: vec![],
+            target_level_overrides: vec![],
+            span_level_field: "".to_string(),
+            event_sampling: EventSamplingConfig::default(),
+            broadcast: BroadcastConfig::default(),
+            error_buffer: ErrorBufferConfig::default(),
+            global_fields: vec![],
+            timer: None,
+            context_fields: None,
+            default_span: None,
+            track_active_spans: false,
+            span_timing: false,
+            seq_field: false,
+            uptime_field: false,
+            max_fields_per_event: None,
+            field_formatter: None,
+            exclude_span_names: vec![],
+            exclude_targets: vec![],
+            level_style: LevelStyle::Full,
+            logfmt_delimiter: ' ',
+            line_ending: LineEnding::Lf,
+            json_source_location: SourceLocation::Auto,
+            span_path_field: false,
+            strict_filter: false,
+            extra_layers: ExtraLayers::default(),
+        }
+    }
 }
 
 impl Config {
@@ -30,30 +380,697 @@ impl Config {
                 on: true,
                 level: "DEBUG".to_string(),
                 dir: "./logs".to_string(),
+                message_field: "msg".to_string(),
+                ..Default::default()
             },
             stderr: StderrConfig {
                 on: true,
                 level: "DEBUG".to_string(),
+                ..Default::default()
+            },
+            otlp_logs: OtlpLogsConfig::default(),
+            webhook: WebhookConfig::default(),
+            sentry: SentryConfig::default(),
+            trace: TraceConfig::default(),
+            app_version: "".to_string(),
+            capture_log_crate: true,
+            log_crate_level: "".to_string(),
+            level_routes: vec![],
+            target_level_overrides: vec![],
+            span_level_field: "".to_string(),
+            event_sampling: EventSamplingConfig::default(),
+            broadcast: BroadcastConfig::default(),
+            error_buffer: ErrorBufferConfig::default(),
+            global_fields: vec![],
+            timer: None,
+            context_fields: None,
+            default_span: None,
+            track_active_spans: false,
+            span_timing: false,
+            seq_field: false,
+            uptime_field: false,
+            max_fields_per_event: None,
+            field_formatter: None,
+            exclude_span_names: vec![],
+            exclude_targets: vec![],
+            level_style: LevelStyle::Full,
+            logfmt_delimiter: ' ',
+            line_ending: LineEnding::Lf,
+            json_source_location: SourceLocation::Auto,
+            span_path_field: false,
+            strict_filter: false,
+            extra_layers: ExtraLayers::default(),
+        }
+    }
+
+    /// Check the level options, so that a typo like `WARM` is reported instead
+    /// of silently disabling the output. Empty options are valid.
+    ///
+    /// `init_logging` reports invalid levels as warnings, see `InitOutcome::warnings`.
+    pub fn validate_levels(&self) -> Result<(), InvalidLogLevel> {
+        let mut levels = vec![
+            ("file.level".to_string(), &self.file.level),
+            ("file.unsampled_level".to_string(), &self.file.unsampled_level),
+            ("stderr.level".to_string(), &self.stderr.level),
+            ("otlp_logs.level".to_string(), &self.otlp_logs.level),
+            ("webhook.level".to_string(), &self.webhook.level),
+            ("broadcast.level".to_string(), &self.broadcast.level),
+            ("error_buffer.level".to_string(), &self.error_buffer.level),
+            ("trace.export_max_level".to_string(), &self.trace.export_max_level),
+            ("log_crate_level".to_string(), &self.log_crate_level),
+        ];
+        for sink in &self.file.sinks {
+            levels.push((format!("file.sinks.{}.level", sink.name), &sink.level));
+        }
+        for (i, route) in self.level_routes.iter().enumerate() {
+            levels.push((format!("level_routes.{}.level", i), &route.level));
+        }
+
+        for (key, directives) in levels {
+            validate_directives(&key, directives)?;
+        }
+        Ok(())
+    }
+
+    /// Check the level options as `validate_levels` does, and the directives of
+    /// `RUST_LOG` as `EnvFilter` parses them, reporting the level of the first
+    /// invalid one, e.g. `dbug` of `mymod=dbug`, instead of ignoring it.
+    pub fn validate_filters(&self) -> Result<(), InvalidLogLevel> {
+        self.validate_levels()?;
+
+        let rust_log = env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+        for directive in rust_log.split(',').map(str::trim) {
+            if directive.is_empty() || EnvFilter::try_new(directive).is_ok() {
+                continue;
+            }
+            let level = match directive.rsplit_once('=') {
+                Some((_, level)) => level,
+                None => directive,
+            };
+            return Err(InvalidLogLevel {
+                key: EnvFilter::DEFAULT_ENV.to_string(),
+                value: level.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The most verbose level routed to `destination`, `None` without `level_routes`.
+    pub fn routed_level(&self, destination: Destination) -> Option<LevelFilter> {
+        if self.level_routes.is_empty() {
+            return None;
+        }
+        let level = self
+            .level_routes
+            .iter()
+            .filter(|route| route.destinations.contains(&destination))
+            .filter_map(|route| LevelFilter::from_str(&route.level).ok())
+            .max()
+            .unwrap_or(LevelFilter::OFF);
+        Some(level)
+    }
+
+    /// The directives of `destination`: its routed level if `level_routes` is
+    /// set, `own` otherwise, e.g. `file.level`.
+    pub(crate) fn routed_directives(&self, destination: Destination, own: &str) -> String {
+        match self.routed_level(destination) {
+            Some(level) => level.to_string(),
+            None => own.to_string(),
+        }
+    }
+
+    /// `log_crate_level` as a filter, an empty or invalid level captures all records.
+    pub fn log_crate_level_filter(&self) -> tracing_log::log::LevelFilter {
+        tracing_log::log::LevelFilter::from_str(&self.log_crate_level)
+            .unwrap_or(tracing_log::log::LevelFilter::Trace)
+    }
+
+    /// The timer of the formatted lines, `timer` or the system clock,
+    /// unless the time is set by `with_event_time`.
+    pub(crate) fn resolved_timer(&self) -> CustomTimer {
+        let timer = self
+            .timer
+            .clone()
+            .unwrap_or_else(|| CustomTimer::new(SystemTime));
+        CustomTimer::new(EventTimeOr(timer))
+    }
+
+    /// with_extra_layer adds a custom layer, e.g. for metrics or alerting,
+    /// to the subscriber built by `init_logging`.
+    ///
+    /// The layer is applied directly on the `Registry`, thus it sees spans
+    /// and events before the built-in layers do.
+    pub fn with_extra_layer(self, layer: BoxedLayer) -> Self {
+        self.extra_layers.0.lock().unwrap().push(layer);
+        self
+    }
+
+    /// dual_output creates a Config writes human readable logs to stderr and
+    /// JSON logs to files in `dir`, each layer filtered by its own level, e.g.
+    /// `Config::dual_output("INFO", "DEBUG", "./logs")`.
+    ///
+    /// Note that `RUST_LOG`, if present, overrides `stderr_level`.
+    pub fn dual_output(stderr_level: &str, file_level: &str, dir: &str) -> Self {
+        Self {
+            file: FileConfig {
+                on: true,
+                level: file_level.to_string(),
+                dir: dir.to_string(),
+                ..Default::default()
             },
+            stderr: StderrConfig {
+                on: true,
+                level: stderr_level.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// dev creates a Config for local development: human readable DEBUG logs
+    /// to stderr, no files. Nothing is exported unless the exporter env vars,
+    /// e.g. `DATABEND_JAEGER_AGENT_ENDPOINT`, are set.
+    pub fn dev() -> Self {
+        Self {
+            file: FileConfig {
+                on: false,
+                ..Default::default()
+            },
+            stderr: StderrConfig {
+                on: true,
+                level: "DEBUG".to_string(),
+                format: LogFormat::Text,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// prod creates a Config for production: INFO logs as JSON(Bunyan) files
+    /// in `DATABEND_LOG_DIR`(or `./logs`), nothing to stderr. Spans go to jaeger and errors
+    /// to sentry as configured by their env vars, e.g. `DATABEND_SENTRY_DSN`.
+    pub fn prod() -> Self {
+        Self {
+            file: FileConfig {
+                on: true,
+                level: "INFO".to_string(),
+                dir: "".to_string(),
+                format: LogFormat::Json,
+                ..Default::default()
+            },
+            stderr: StderrConfig {
+                on: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// trace_to_file creates a Config for deep debugging: TRACE events are
+    /// written to files in `dir`, while jaeger and sentry are capped at DEBUG
+    /// so that large payloads never leave the box.
+    pub fn trace_to_file(dir: &str) -> Self {
+        Self {
+            file: FileConfig {
+                on: true,
+                level: "TRACE".to_string(),
+                dir: dir.to_string(),
+                ..Default::default()
+            },
+            trace: TraceConfig {
+                export_max_level: "DEBUG".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Output format of file and stderr logging.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable text, the default of `tracing_subscriber::fmt`.
+    #[default]
+    Text,
+    /// JSON lines, in Bunyan format for files.
+    ///
+    /// Integer, float and bool fields are kept as JSON numbers and bools,
+    /// e.g. `count = 5` becomes `"count":5`. Fields recorded with `%` or `?`
+    /// are formatted, thus always written as strings.
+    Json,
+    /// `key=value` pairs.
+    Logfmt,
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+            LogFormat::Logfmt => write!(f, "logfmt"),
+        }
+    }
+}
+
+/// An output of the events, see `Config::level_routes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Destination {
+    /// The main log files.
+    File,
+    Stderr,
+    Sentry,
+    Webhook,
+    OtlpLogs,
+    Broadcast,
+}
+
+impl Display for Destination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Destination::File => write!(f, "file"),
+            Destination::Stderr => write!(f, "stderr"),
+            Destination::Sentry => write!(f, "sentry"),
+            Destination::Webhook => write!(f, "webhook"),
+            Destination::OtlpLogs => write!(f, "otlp_logs"),
+            Destination::Broadcast => write!(f, "broadcast"),
+        }
+    }
+}
+
+/// Send the events of `level` and the more severe ones to `destinations`.
+///
+/// A destination listed by several routes receives the events of the most
+/// verbose of their levels, e.g. with `TRACE => [file]` and
+/// `ERROR => [file, sentry]` the file receives all events. A destination
+/// listed by no route receives none.
+///
+/// The routes replace the level options of the destinations, e.g.
+/// `file.level`, but do not enable them: the webhook still needs
+/// `webhook.on`. `RUST_LOG` still sets the stderr level if present, and the
+/// level of sentry is also capped by `trace.export_max_level`. Events routed to
+/// `FileConfig::sinks` are not written to the main files either way.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct LevelRoute {
+    pub level: String,
+    pub destinations: Vec<Destination>,
+}
+
+impl LevelRoute {
+    pub fn new(level: &str, destinations: &[Destination]) -> Self {
+        Self {
+            level: level.to_string(),
+            destinations: destinations.to_vec(),
+        }
+    }
+}
+
+impl Display for LevelRoute {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let destinations = self
+            .destinations
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}=>[{}]", self.level, destinations.join(", "))
+    }
+}
+
+/// How levels are written in the text and logfmt lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LevelStyle {
+    /// `ERROR`, `WARN`, `INFO`, `DEBUG`, `TRACE`.
+    #[default]
+    Full,
+    /// `E`, `W`, `I`, `D`, `T`.
+    Short,
+    /// `error`, `warn`, `info`, `debug`, `trace`.
+    Lowercase,
+}
+
+impl LevelStyle {
+    pub fn render(&self, level: &Level) -> &'static str {
+        match (self, *level) {
+            (LevelStyle::Full, level) => level.as_str(),
+            (LevelStyle::Short, Level::ERROR) => "E",
+            (LevelStyle::Short, Level::WARN) => "W",
+            (LevelStyle::Short, Level::INFO) => "I",
+            (LevelStyle::Short, Level::DEBUG) => "D",
+            (LevelStyle::Short, _) => "T",
+            (LevelStyle::Lowercase, Level::ERROR) => "error",
+            (LevelStyle::Lowercase, Level::WARN) => "warn",
+            (LevelStyle::Lowercase, Level::INFO) => "info",
+            (LevelStyle::Lowercase, Level::DEBUG) => "debug",
+            (LevelStyle::Lowercase, _) => "trace",
+        }
+    }
+
+    /// The width of the level column of the text lines.
+    pub fn width(&self) -> usize {
+        match self {
+            LevelStyle::Short => 1,
+            _ => 5,
+        }
+    }
+}
+
+impl Display for LevelStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelStyle::Full => write!(f, "full"),
+            LevelStyle::Short => write!(f, "short"),
+            LevelStyle::Lowercase => write!(f, "lowercase"),
+        }
+    }
+}
+
+/// How the text lines end.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// `\n`.
+    #[default]
+    Lf,
+    /// `\r\n`.
+    Crlf,
+}
+
+impl Display for LineEnding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "lf"),
+            LineEnding::Crlf => write!(f, "crlf"),
+        }
+    }
+}
+
+/// Which source location members the JSON lines have.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceLocation {
+    /// As written by the formatter.
+    #[default]
+    Auto,
+    /// Always `file`, `line` and `module_path`, if known, including for the
+    /// records of the `log` crate.
+    Include,
+    /// Never any, to reduce the volume.
+    Exclude,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceLocation::Auto => write!(f, "auto"),
+            SourceLocation::Include => write!(f, "include"),
+            SourceLocation::Exclude => write!(f, "exclude"),
+        }
+    }
+}
+
+/// A log level parsed from a config string, case-insensitive.
+///
+/// The level options of the config are strings as they also accept `EnvFilter`
+/// directives, e.g. `INFO,hyper=warn`. Use `LogLevel` to set them without typos,
+/// e.g. `LogLevel::Warn.into()`, and `Config::validate_levels` to check them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = InvalidLogLevel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "ERROR" => Ok(LogLevel::Error),
+            "WARN" => Ok(LogLevel::Warn),
+            "INFO" => Ok(LogLevel::Info),
+            "DEBUG" => Ok(LogLevel::Debug),
+            "TRACE" => Ok(LogLevel::Trace),
+            _ => Err(InvalidLogLevel {
+                key: "".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLevel::Error => write!(f, "ERROR"),
+            LogLevel::Warn => write!(f, "WARN"),
+            LogLevel::Info => write!(f, "INFO"),
+            LogLevel::Debug => write!(f, "DEBUG"),
+            LogLevel::Trace => write!(f, "TRACE"),
+        }
+    }
+}
+
+impl From<LogLevel> for String {
+    fn from(level: LogLevel) -> Self {
+        level.to_string()
+    }
+}
+
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Trace => Level::TRACE,
+        }
+    }
+}
+
+/// A level that is not one of `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidLogLevel {
+    /// The config option, e.g. `file.level`, empty if parsed on its own.
+    pub key: String,
+    pub value: String,
+}
+
+impl Display for InvalidLogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.key.is_empty() {
+            write!(f, "{}: ", self.key)?;
+        }
+        write!(
+            f,
+            "invalid log level `{}`, expected one of ERROR, WARN, INFO, DEBUG, TRACE",
+            self.value
+        )
+    }
+}
+
+impl std::error::Error for InvalidLogLevel {}
+
+/// Check the levels of `directives`, e.g. `INFO,hyper=warn`.
+///
+/// `OFF` is accepted too. A directive without level, e.g. `hyper`, is taken
+/// as a level thus rejected, write `hyper=trace` instead.
+fn validate_directives(key: &str, directives: &str) -> Result<(), InvalidLogLevel> {
+    for directive in directives.split(',').map(str::trim) {
+        let level = match directive.rsplit_once('=') {
+            Some((_, level)) => level,
+            None => directive,
+        };
+        if directive.is_empty() || level.eq_ignore_ascii_case("off") {
+            continue;
         }
+        level.parse::<LogLevel>().map_err(|e| InvalidLogLevel {
+            key: key.to_string(),
+            ..e
+        })?;
     }
+    Ok(())
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
 pub struct FileConfig {
     pub on: bool,
     pub level: String,
+    /// Empty means using env `DATABEND_LOG_DIR`, see `FileConfig::resolved_dir`.
     pub dir: String,
-    // TODO: Add format support in the future, before that we use `json`
-    // pub format: String,
+    /// `json`(Bunyan) by default, or `text`, `logfmt`.
+    pub format: LogFormat,
+    /// Key of the primary text in the `json` records, `msg` by default.
+    ///
+    /// Any other value makes the records incompatible with Bunyan tools.
+    pub message_field: String,
+    /// Max number of lines buffered in memory before they are written to
+    /// the file by the background worker.
+    ///
+    /// The worker writes and flushes as soon as lines are available, there is
+    /// no flush interval to tune: a smaller limit bounds the latency and memory
+    /// of a burst, a larger one absorbs bursts without blocking or dropping.
+    pub buffered_lines_limit: usize,
+    /// Drop lines instead of blocking the caller when the buffer is full.
+    pub lossy: bool,
+    /// Max bytes buffered in memory by all file writers, including the query
+    /// logger. Lines are dropped once exceeded, see `dropped_log_lines`.
+    /// 0 means unbounded.
+    pub max_buffered_bytes: u64,
+    /// How often a new file is started: `minutely`, `hourly`, `daily` or `never`.
+    pub rotation: String,
+    /// Max number of files to keep, the oldest ones are deleted on rotation.
+    /// 0 means keeping all files.
+    pub max_files: usize,
+    /// Max bytes of all the files of a log, the oldest ones are deleted on
+    /// rotation until they fit, together with `max_files`: the stricter wins.
+    /// The current file is never deleted, it may exceed the limit by itself.
+    /// `None` means no limit.
+    pub max_total_bytes: Option<u64>,
+    /// Name the rotated files in local time instead of UTC, e.g. for
+    /// `databend-query.2022-10-01-08`. The timestamps of the lines are not changed.
+    ///
+    /// The local offset is read once at init, after a DST change the names are
+    /// off by an hour until a restart. Some unix platforms can only read it
+    /// while the process is single threaded, UTC is used otherwise.
+    pub local_time_file_names: bool,
+    /// Max level of the events written for traces not sampled by the tracer,
+    /// e.g. `WARN`, or `OFF` to drop them all. Empty means writing them all,
+    /// as for sampled traces.
+    pub unsampled_level: String,
+    /// Start every query log line with an RFC3339 UTC timestamp.
+    pub query_log_time: bool,
+    /// Events of some targets written to their own files instead of the main
+    /// one, e.g. `security` to an `audit` file.
+    pub sinks: Vec<FileSinkConfig>,
+    /// Field whose value replaces the Bunyan `name` of the `json` records,
+    /// e.g. the `tenant` field of a request span, so that records can be indexed
+    /// per tenant. Records without the field keep the app name. Empty disables it.
+    pub name_field: String,
+    /// Write and flush `ERROR` lines synchronously, bypassing the buffer, so
+    /// that the last error is on disk before a likely crash. It costs some
+    /// throughput when errors are frequent.
+    pub sync_error_flush: bool,
+    /// What to do when a write fails because the disk of the log dir is full,
+    /// see `disk_full_incidents`.
+    pub disk_full_policy: DiskFullPolicy,
+    /// Also write the events of a `text` or `logfmt` main file as `json`
+    /// records to the files of `<name>.json`, e.g. `databend-query.json.2022-10-01-08`,
+    /// rotated together: the lines of an event are in files of the same period.
+    /// Both are pruned by `max_files` and `max_total_bytes` on their own.
+    pub json_sidecar: bool,
+    /// Write a `span_open` and a `span_close` JSON record for every span, with
+    /// its id, parent id, name and fields, to the `json` main file or to the
+    /// JSON sidecar, to rebuild the span tree offline, see `SpanEventsLayer`.
+    pub span_events: bool,
+}
+
+/// A file in the log dir receiving the events of some targets.
+///
+/// The file is rotated and buffered with the options of the main file,
+/// lines are written in the sink `format`.
+#[derive(Clone, Debug, PartialEq, Eq, Default, serde::Serialize)]
+pub struct FileSinkConfig {
+    /// Prefix of the file names, e.g. `audit`.
+    pub name: String,
+    /// Target prefixes routed to this file, e.g. `security` also routes
+    /// `security::login`. Events of a target matching several sinks are
+    /// written to all of them, and never to the main file.
+    pub targets: Vec<String>,
+    pub level: String,
+    /// `text` by default, or `json`, `logfmt`. JSON lines are not in Bunyan
+    /// format, they are written like the stderr ones.
+    pub format: LogFormat,
+}
+
+impl FileSinkConfig {
+    /// Whether the events of `target` are routed to this sink.
+    pub fn routes(&self, target: &str) -> bool {
+        self.targets
+            .iter()
+            .any(|prefix| match target.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+    }
+}
+
+impl Display for FileSinkConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}(targets=[{}], level={}, format={})",
+            self.name,
+            self.targets.join(","),
+            self.level,
+            self.format
+        )
+    }
+}
+
+/// Env var providing the log dir if it is not set in the config.
+pub const LOG_DIR_ENV: &str = "DATABEND_LOG_DIR";
+
+impl FileConfig {
+    /// The dir to write logs to, in order of precedence:
+    /// `dir` if not empty, then env `DATABEND_LOG_DIR` if not empty, then `./logs`.
+    pub fn resolved_dir(&self) -> String {
+        if !self.dir.is_empty() {
+            return self.dir.clone();
+        }
+        match env::var(LOG_DIR_ENV) {
+            Ok(dir) if !dir.is_empty() => dir,
+            _ => "./logs".to_string(),
+        }
+    }
+
+    /// `unsampled_level` as a filter, an empty or invalid level enables all events.
+    pub fn unsampled_level_filter(&self) -> LevelFilter {
+        // `LevelFilter` parses an empty string as `ERROR`.
+        match self.unsampled_level.as_str() {
+            "" => LevelFilter::TRACE,
+            level => LevelFilter::from_str(level).unwrap_or(LevelFilter::TRACE),
+        }
+    }
 }
 
 impl Display for FileConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "enabled={}, level={}, dir={}",
-            self.on, self.level, self.dir
+            "enabled={}, level={}, dir={}, format={}, message_field={}, buffered_lines_limit={}, lossy={}, max_buffered_bytes={}, rotation={}, max_files={}, max_total_bytes={}, local_time_file_names={}, unsampled_level={}, query_log_time={}, sinks=[{}], name_field={}, sync_error_flush={}, disk_full_policy={}, json_sidecar={}, span_events={}",
+            self.on,
+            self.level,
+            self.dir,
+            self.format,
+            self.message_field,
+            self.buffered_lines_limit,
+            self.lossy,
+            self.max_buffered_bytes,
+            self.rotation,
+            self.max_files,
+            match self.max_total_bytes {
+                None => "unlimited".to_string(),
+                Some(max) => max.to_string(),
+            },
+            self.local_time_file_names,
+            self.unsampled_level,
+            self.query_log_time,
+            self.sinks
+                .iter()
+                .map(|sink| sink.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.name_field,
+            self.sync_error_flush,
+            self.disk_full_policy,
+            self.json_sidecar,
+            self.span_events
         )
     }
 }
@@ -64,6 +1081,51 @@ impl Default for FileConfig {
             on: true,
             level: "INFO".to_string(),
             dir: "./logs".to_string(),
+            format: LogFormat::Json,
+            message_field: "msg".to_string(),
+            buffered_lines_limit: 128_000,
+            lossy: true,
+            max_buffered_bytes: 0,
+            rotation: "hourly".to_string(),
+            max_files: 0,
+            max_total_bytes: None,
+            local_time_file_names: false,
+            unsampled_level: "".to_string(),
+            query_log_time: false,
+            sinks: vec![],
+            name_field: "".to_string(),
+            sync_error_flush: false,
+            disk_full_policy: DiskFullPolicy::default(),
+            json_sidecar: false,
+            span_events: false,
+        }
+    }
+}
+
+/// What the file writers do when the disk of the log dir is full.
+///
+/// Only the files are affected, stderr and the exporters keep going.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskFullPolicy {
+    /// Drop the file lines, and try writing again every 10 seconds.
+    #[default]
+    StopLogging,
+    /// Retry the write until it succeeds. The buffer fills up, then new lines
+    /// are dropped if `lossy`, or the logging threads block otherwise.
+    Block,
+    /// Delete the oldest rotated files of the log and of its JSON sidecar until
+    /// the write succeeds, regardless of `max_files`. Lines are dropped as with
+    /// `StopLogging` once only the current files are left.
+    DeleteOldest,
+}
+
+impl Display for DiskFullPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiskFullPolicy::StopLogging => write!(f, "stop_logging"),
+            DiskFullPolicy::Block => write!(f, "block"),
+            DiskFullPolicy::DeleteOldest => write!(f, "delete_oldest"),
         }
     }
 }
@@ -72,15 +1134,18 @@ impl Default for FileConfig {
 pub struct StderrConfig {
     pub on: bool,
     pub level: String,
-    // TODO: Add format support in the future, before that we use `text`
-    // pub format: String,
+    /// `text` by default, or `json`, `logfmt`.
+    pub format: LogFormat,
+    /// Colors of the `text` lines, `None` keeps the format of `tracing_subscriber`.
+    /// Lines are only colored if stderr is a terminal, see `is_terminal_stderr`.
+    pub theme: Option<AnsiTheme>,
 }
 
 impl Display for StderrConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "enabled={}{}, level={}",
+            "enabled={}{}, level={}, format={}, theme={}",
             self.on,
             if !self.on {
                 "(To enable: LOG_STDERR_ON=true or RUST_LOG=info)"
@@ -88,11 +1153,525 @@ impl Display for StderrConfig {
                 ""
             },
             self.level,
+            self.format,
+            match &self.theme {
+                None => "default".to_string(),
+                Some(theme) => format!("{:?}", theme),
+            },
         )
     }
 }
 
 impl Default for StderrConfig {
+    fn default() -> Self {
+        Self {
+            on: false,
+            level: "INFO".to_string(),
+            format: LogFormat::Text,
+            theme: None,
+        }
+    }
+}
+
+/// Colors of the stderr `text` lines, per level.
+///
+/// `AnsiTheme::default()` has the colors of `tracing_subscriber`, timestamps and
+/// targets dimmed. `high_contrast` and `solarized` are alternatives for terminals
+/// where they are hard to read, or build one with `AnsiColor::Rgb`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct AnsiTheme {
+    pub error: AnsiColor,
+    pub warn: AnsiColor,
+    pub info: AnsiColor,
+    pub debug: AnsiColor,
+    pub trace: AnsiColor,
+    /// Dim the timestamp and the target.
+    pub dim_metadata: bool,
+}
+
+impl AnsiTheme {
+    /// Bright colors and no dimming, for low contrast terminals.
+    pub fn high_contrast() -> Self {
+        Self {
+            error: AnsiColor::BrightRed,
+            warn: AnsiColor::BrightYellow,
+            info: AnsiColor::BrightGreen,
+            debug: AnsiColor::BrightCyan,
+            trace: AnsiColor::BrightMagenta,
+            dim_metadata: false,
+        }
+    }
+
+    /// The accent colors of the Solarized palette.
+    pub fn solarized() -> Self {
+        Self {
+            error: AnsiColor::Rgb(220, 50, 47),
+            warn: AnsiColor::Rgb(181, 137, 0),
+            info: AnsiColor::Rgb(133, 153, 0),
+            debug: AnsiColor::Rgb(38, 139, 210),
+            trace: AnsiColor::Rgb(108, 113, 196),
+            dim_metadata: true,
+        }
+    }
+
+    pub fn color(&self, level: &Level) -> AnsiColor {
+        match *level {
+            Level::ERROR => self.error,
+            Level::WARN => self.warn,
+            Level::INFO => self.info,
+            Level::DEBUG => self.debug,
+            _ => self.trace,
+        }
+    }
+}
+
+impl Default for AnsiTheme {
+    fn default() -> Self {
+        Self {
+            error: AnsiColor::Red,
+            warn: AnsiColor::Yellow,
+            info: AnsiColor::Green,
+            debug: AnsiColor::Blue,
+            trace: AnsiColor::Magenta,
+            dim_metadata: true,
+        }
+    }
+}
+
+/// A foreground color of the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum AnsiColor {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// A 24 bit color, not supported by all terminals.
+    Rgb(u8, u8, u8),
+}
+
+impl AnsiColor {
+    /// The escape sequence switching to this color.
+    pub fn escape(&self) -> String {
+        let code = match self {
+            AnsiColor::Red => 31,
+            AnsiColor::Green => 32,
+            AnsiColor::Yellow => 33,
+            AnsiColor::Blue => 34,
+            AnsiColor::Magenta => 35,
+            AnsiColor::Cyan => 36,
+            AnsiColor::White => 37,
+            AnsiColor::BrightRed => 91,
+            AnsiColor::BrightGreen => 92,
+            AnsiColor::BrightYellow => 93,
+            AnsiColor::BrightBlue => 94,
+            AnsiColor::BrightMagenta => 95,
+            AnsiColor::BrightCyan => 96,
+            AnsiColor::BrightWhite => 97,
+            AnsiColor::Rgb(r, g, b) => return format!("\x1b[38;2;{};{};{}m", r, g, b),
+        };
+        format!("\x1b[{}m", code)
+    }
+}
+
+/// Config for exporting events as OTLP log records.
+///
+/// Only takes effect when built with the `otlp-logs` feature.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct OtlpLogsConfig {
+    pub on: bool,
+    pub level: String,
+    /// gRPC endpoint of the OTLP collector, e.g. `http://127.0.0.1:4317`.
+    pub endpoint: String,
+}
+
+impl Display for OtlpLogsConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "enabled={}, level={}, endpoint={}",
+            self.on, self.level, self.endpoint
+        )
+    }
+}
+
+impl Default for OtlpLogsConfig {
+    fn default() -> Self {
+        Self {
+            on: false,
+            level: "INFO".to_string(),
+            endpoint: "http://127.0.0.1:4317".to_string(),
+        }
+    }
+}
+
+/// Config for posting events to a webhook, e.g. a Slack incoming webhook.
+///
+/// Only takes effect when built with the `http-sink` feature.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct WebhookConfig {
+    pub on: bool,
+    pub level: String,
+    /// URL the events are posted to as JSON, see `WebhookLayer`.
+    pub url: String,
+    /// Max number of events posted per minute, the others are dropped.
+    pub max_events_per_minute: usize,
+}
+
+impl Display for WebhookConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "enabled={}, level={}, url={}, max_events_per_minute={}",
+            self.on, self.level, self.url, self.max_events_per_minute
+        )
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            on: false,
+            level: "WARN".to_string(),
+            url: "".to_string(),
+            max_events_per_minute: 60,
+        }
+    }
+}
+
+/// Config for the exported traces.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct TraceConfig {
+    /// Names of the span fields exported as span attributes(jaeger tags).
+    /// Other fields are only kept in logs. Empty means exporting all fields.
+    pub tag_allowlist: Vec<String>,
+    /// Max UDP packet size sent to the jaeger agent, 0 means the exporter default.
+    /// Batches are split to fit, a single span larger than this is dropped.
+    pub jaeger_max_packet_size: usize,
+    /// Jaeger collector HTTP endpoint, e.g. `http://localhost:14268/api/traces`.
+    /// If set, spans are sent to the collector instead of the UDP agent.
+    pub jaeger_collector_endpoint: String,
+    /// A custom sampler for business rules, e.g. always sampling errors.
+    /// Overrides the sampler configured by `OTEL_TRACES_SAMPLER`,
+    /// which is enough for ratio based sampling.
+    /// It is used as is, `honor_parent_sampling` does not apply, wrap it in
+    /// `Sampler::ParentBased` to keep the decision of the parent.
+    pub sampler: Option<CustomSampler>,
+    /// The most verbose level sent off-box to jaeger(or OTLP) and sentry,
+    /// e.g. `DEBUG` keeps TRACE payloads in the local files only.
+    /// Empty or invalid means no limit.
+    pub export_max_level: String,
+    /// Baggage keys added as fields to the text, logfmt and stderr JSON lines,
+    /// e.g. `tenant`. Bunyan JSON file records do not have them.
+    pub baggage_log_fields: Vec<String>,
+    /// Dir of the files spans are written to as OTLP JSON, one batch per line,
+    /// e.g. for importing into jaeger or tempo in air-gapped environments.
+    /// Only used if no other exporter is configured, files are rotated with the
+    /// `file` options. Empty disables it.
+    pub otlp_json_dir: String,
+    /// Max retries of a span batch failing to export to jaeger or OTLP, with
+    /// exponential backoff. Batches failing after the retries are dropped and
+    /// counted by `trace_export_failures`. 0 disables retrying.
+    ///
+    /// Not applied to `jaeger_collector_endpoint`, whose failed batches are
+    /// not counted either.
+    pub export_max_retries: usize,
+    /// OTLP gRPC endpoints spans are mirrored to, in addition to the exporter
+    /// selected by the env vars or the options above, e.g. a central collector
+    /// while migrating off a local jaeger. Unused if no exporter is selected,
+    /// or if it is `jaeger_collector_endpoint`.
+    ///
+    /// Every mirror has its own batch queue and export task: sampled spans are
+    /// copied and encoded once per exporter, costing memory and CPU in proportion.
+    /// An invalid mirror is skipped with a warning, the other exporters are kept.
+    pub mirror_otlp_endpoints: Vec<String>,
+    /// Seconds between the HTTP/2 pings of the OTLP gRPC connections, sent even
+    /// while idle, also used as the TCP keepalive. Keep it below the idle timeout
+    /// of the load balancers on the way, 60 seconds for most. 0 disables the pings.
+    pub otlp_keepalive_interval_secs: u64,
+    /// Seconds to wait for the answer to a ping before the connection is closed.
+    pub otlp_keepalive_timeout_secs: u64,
+    /// Seconds to wait for a connection to establish. A closed connection is
+    /// reconnected on the next export, a batch failing to connect is retried as
+    /// configured by `export_max_retries`.
+    pub otlp_connect_timeout_secs: u64,
+    /// Add the message of the first `ERROR` event of the exported spans to their
+    /// `Error` status, see `ErrorStatusLayer`. Such spans are marked as failed anyway.
+    pub error_events_set_status: bool,
+    /// Add the `max_event_level` and `error` attributes to the exported spans,
+    /// with the most severe level of their events, see `ErrorStatusLayer`.
+    pub event_level_attributes: bool,
+    /// Make the sampler parent based: spans with a parent, e.g. a remote one
+    /// extracted by `extract_remote_span_as_parent` from a `traceparent` with
+    /// flags `00`, keep the decision of the parent, only root spans are sampled
+    /// by `OTEL_TRACES_SAMPLER`. Disable it to sample every span on its own.
+    /// An explicit `always_on`, `always_off` or `traceidratio` `OTEL_TRACES_SAMPLER`,
+    /// or a custom `sampler`, is not made parent based.
+    pub honor_parent_sampling: bool,
+    /// The propagator set when a trace exporter is enabled, used by
+    /// `inject_span_to_tonic_request` and `extract_remote_span_as_parent`.
+    ///
+    /// It is process-global: it replaces the propagator set by another
+    /// library, and the last `init_logging` wins.
+    pub propagator: Propagator,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            tag_allowlist: vec![],
+            jaeger_max_packet_size: 0,
+            jaeger_collector_endpoint: "".to_string(),
+            sampler: None,
+            export_max_level: "".to_string(),
+            baggage_log_fields: vec![],
+            otlp_json_dir: "".to_string(),
+            export_max_retries: 0,
+            mirror_otlp_endpoints: vec![],
+            otlp_keepalive_interval_secs: 30,
+            otlp_keepalive_timeout_secs: 10,
+            otlp_connect_timeout_secs: 5,
+            error_events_set_status: true,
+            event_level_attributes: false,
+            honor_parent_sampling: true,
+            propagator: Propagator::default(),
+        }
+    }
+}
+
+/// The OpenTelemetry propagators of the trace context across processes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Propagator {
+    /// The W3C `traceparent` and `tracestate` headers.
+    TraceContext,
+    /// The W3C `baggage` header only, spans are not linked across processes.
+    Baggage,
+    /// Both `TraceContext` and `Baggage`.
+    #[default]
+    Composite,
+}
+
+impl Display for Propagator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Propagator::TraceContext => write!(f, "trace_context"),
+            Propagator::Baggage => write!(f, "baggage"),
+            Propagator::Composite => write!(f, "composite"),
+        }
+    }
+}
+
+impl TraceConfig {
+    /// `export_max_level` as a filter, an empty or invalid level enables all events.
+    pub fn export_level_filter(&self) -> LevelFilter {
+        // `LevelFilter` parses an empty string as `ERROR`.
+        match self.export_max_level.as_str() {
+            "" => LevelFilter::TRACE,
+            level => LevelFilter::from_str(level).unwrap_or(LevelFilter::TRACE),
+        }
+    }
+}
+
+/// A user supplied OpenTelemetry sampler.
+///
+/// Samplers can not be compared, two `CustomSampler`s are equal if they share
+/// the same sampler, e.g. clones of one config. They can not be serialized
+/// either, their `Debug` output is used instead.
+#[derive(Clone, Debug)]
+pub struct CustomSampler(pub Arc<dyn ShouldSample>);
+
+impl CustomSampler {
+    pub fn new(sampler: impl ShouldSample + 'static) -> Self {
+        Self(Arc::new(sampler))
+    }
+}
+
+impl PartialEq for CustomSampler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CustomSampler {}
+
+impl ShouldSample for CustomSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        links: &[Link],
+        instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        self.0.should_sample(
+            parent_context,
+            trace_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+            instrumentation_library,
+        )
+    }
+}
+
+impl serde::Serialize for CustomSampler {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", self.0))
+    }
+}
+
+impl Display for TraceConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tag_allowlist=[{}], jaeger_max_packet_size={}, jaeger_collector_endpoint={}, sampler={}, export_max_level={}, baggage_log_fields=[{}], otlp_json_dir={}, export_max_retries={}, mirror_otlp_endpoints=[{}], otlp_keepalive_interval_secs={}, otlp_keepalive_timeout_secs={}, otlp_connect_timeout_secs={}, error_events_set_status={}, event_level_attributes={}, honor_parent_sampling={}, propagator={}",
+            self.tag_allowlist.join(","),
+            self.jaeger_max_packet_size,
+            self.jaeger_collector_endpoint,
+            match &self.sampler {
+                None => "env".to_string(),
+                Some(sampler) => format!("{:?}", sampler.0),
+            },
+            self.export_max_level,
+            self.baggage_log_fields.join(","),
+            self.otlp_json_dir,
+            self.export_max_retries,
+            self.mirror_otlp_endpoints.join(","),
+            self.otlp_keepalive_interval_secs,
+            self.otlp_keepalive_timeout_secs,
+            self.otlp_connect_timeout_secs,
+            self.error_events_set_status,
+            self.event_level_attributes,
+            self.honor_parent_sampling,
+            self.propagator
+        )
+    }
+}
+
+/// Config for sampling events by the value of a field.
+#[derive(Clone, Debug, PartialEq, Eq, Default, serde::Serialize)]
+pub struct EventSamplingConfig {
+    /// Name of the field to sample by, e.g. `endpoint`. Empty disables sampling.
+    pub field: String,
+    /// `(value, n)` pairs: only 1 in `n` events with the field equal to `value` are
+    /// kept, e.g. `("GET /health", 100)` keeps 1%. `n = 0` drops them all.
+    pub one_in: Vec<(String, u64)>,
+}
+
+impl Display for EventSamplingConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "field={}, one_in=[{}]",
+            self.field,
+            self.one_in
+                .iter()
+                .map(|(value, n)| format!("{}:{}", value, n))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+/// Config of the events sent to sentry, when `DATABEND_SENTRY_DSN` is set.
+///
+/// Only the sentry events and breadcrumbs are changed, files, stderr and the
+/// other exporters keep the full messages and fields.
+#[derive(Clone, Debug, PartialEq, Eq, Default, serde::Serialize)]
+pub struct SentryConfig {
+    /// Max bytes of the message, e.g. to keep a huge SQL from breaking the
+    /// grouping of the issues. Longer ones are cut at a char boundary.
+    /// 0 means no limit.
+    pub max_message_len: usize,
+    /// Max bytes of the string value of every other field. 0 means no limit.
+    pub max_field_len: usize,
+    /// Names of the fields never sent to sentry, e.g. `sql`.
+    pub strip_fields: Vec<String>,
+}
+
+impl Display for SentryConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "max_message_len={}, max_field_len={}, strip_fields=[{}]",
+            self.max_message_len,
+            self.max_field_len,
+            self.strip_fields.join(",")
+        )
+    }
+}
+
+/// Config for holding back the verbose lines of the requests written to the
+/// main log file: they are written once an `ERROR` event happens inside the
+/// request span, and discarded if it closes without one.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct ErrorBufferConfig {
+    /// Names of the spans buffering the lines of the events inside them,
+    /// e.g. `handle_request`. Empty disables the buffering.
+    pub span_names: Vec<String>,
+    /// Events at this level or more verbose are buffered, e.g. `DEBUG`
+    /// buffers `DEBUG` and `TRACE` ones. Invalid means `DEBUG`.
+    pub level: String,
+    /// Max bytes buffered per span, the oldest lines are dropped beyond it.
+    pub max_bytes: usize,
+}
+
+impl ErrorBufferConfig {
+    /// The least verbose level buffered.
+    pub(crate) fn buffered_level(&self) -> Level {
+        Level::from_str(&self.level).unwrap_or(Level::DEBUG)
+    }
+}
+
+impl Display for ErrorBufferConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "span_names=[{}], level={}, max_bytes={}",
+            self.span_names.join(","),
+            self.level,
+            self.max_bytes
+        )
+    }
+}
+
+impl Default for ErrorBufferConfig {
+    fn default() -> Self {
+        Self {
+            span_names: vec![],
+            level: "DEBUG".to_string(),
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Config for forwarding events to the consumers of `subscribe_events`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct BroadcastConfig {
+    pub on: bool,
+    pub level: String,
+}
+
+impl Display for BroadcastConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "enabled={}, level={}", self.on, self.level)
+    }
+}
+
+impl Default for BroadcastConfig {
     fn default() -> Self {
         Self {
             on: false,
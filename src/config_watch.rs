@@ -0,0 +1,205 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+
+use notify::Event;
+use notify::EventKind;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+use tracing::info;
+use tracing::warn;
+
+use crate::Config;
+use crate::ReloadHandle;
+use crate::INTERNAL_TARGET;
+
+/// Config keys applied without a restart, as paths in the config file.
+const RELOADABLE_KEYS: [&str; 3] = ["file.level", "stderr.level", "file.dir"];
+
+/// Max wait for the lines buffered for the previous dir, see `ReloadHandle::set_file_dir`.
+const DIR_SWAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A save is reported as several events, e.g. truncate then write: the file is
+/// read once no event has come for this long.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watch a TOML config file laid out as `Config`, and apply the level changes
+/// when it is modified, e.g.:
+///
+/// ```toml
+/// [file]
+/// level = "DEBUG"
+/// [stderr]
+/// level = "WARN,common_tracing=debug"
+/// ```
+///
+/// Only `file.level`, `stderr.level` and `file.dir` are applied live, the main
+/// log files are moved to the new dir without losing lines. Changes of any
+/// other key are reported as a warning, they require a restart.
+///
+/// The file is watched until the returned watcher is dropped.
+pub fn watch_config_file(
+    path: impl AsRef<Path>,
+    handle: ReloadHandle,
+) -> notify::Result<RecommendedWatcher> {
+    let path = path.as_ref().to_path_buf();
+    let file_name = path.file_name().map(|name| name.to_os_string());
+
+    // Editors often replace the file instead of writing it, watch the dir instead.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                warn!(target: INTERNAL_TARGET, "failed to watch config file: {}", e);
+                return;
+            }
+        };
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+        if !event
+            .paths
+            .iter()
+            .any(|p| p.file_name().map(|name| name.to_os_string()) == file_name)
+        {
+            return;
+        }
+        let _ = tx.send(());
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    thread::Builder::new()
+        .name("config-watch".to_string())
+        .spawn(move || {
+            // The config as last applied, the file is compared to it.
+            let mut applied = handle.config.clone();
+            // Ends once the watcher, holding the sender, is dropped.
+            while rx.recv().is_ok() {
+                loop {
+                    match rx.recv_timeout(DEBOUNCE) {
+                        Ok(_) => continue,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                apply_config_file(&path, &handle, &mut applied);
+            }
+        })
+        .map_err(notify::Error::io)?;
+
+    Ok(watcher)
+}
+
+/// Apply the changes of the file from `applied`, and record them in `applied`.
+fn apply_config_file(path: &Path, handle: &ReloadHandle, applied: &mut Config) {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        // Removed or being replaced, wait for the next event.
+        Err(_) => return,
+    };
+    let new = match content.parse::<toml::Value>() {
+        Ok(value) => serde_json::to_value(value).unwrap_or_default(),
+        Err(e) => {
+            warn!(target: INTERNAL_TARGET, "invalid config file {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let current = serde_json::to_value(&*applied).unwrap_or_default();
+
+    let mut changed = vec![];
+    collect_changes("", &new, &current, &mut changed);
+
+    for key in changed {
+        let value = lookup(&new, &key).and_then(|v| v.as_str().map(|s| s.to_string()));
+        let result = match (key.as_str(), value) {
+            ("file.level", Some(level)) => Some(
+                handle
+                    .set_file_level(&level)
+                    .map(|_| applied.file.level = level)
+                    .map_err(|e| e.to_string()),
+            ),
+            ("stderr.level", Some(level)) => Some(
+                handle
+                    .set_stderr_level(&level)
+                    .map(|_| applied.stderr.level = level)
+                    .map_err(|e| e.to_string()),
+            ),
+            ("file.dir", Some(dir)) if !dir.is_empty() => Some(
+                handle
+                    .set_file_dir(&dir, DIR_SWAP_TIMEOUT)
+                    .map(|_| applied.file.dir = dir)
+                    .map_err(|e| e.to_string()),
+            ),
+            _ => None,
+        };
+        match result {
+            Some(Ok(_)) => info!(target: INTERNAL_TARGET, "applied config change of {}", key),
+            Some(Err(e)) => warn!(target: INTERNAL_TARGET, "invalid {}: {}", key, e),
+            None => warn!(
+                target: INTERNAL_TARGET,
+                "config change of {} requires a restart, reloadable: {}",
+                key,
+                RELOADABLE_KEYS.join(", ")
+            ),
+        }
+    }
+}
+
+/// Collect the dotted paths of the values in `new` differing from `current`.
+/// Keys missing from `new` keep their current value.
+fn collect_changes(
+    prefix: &str,
+    new: &serde_json::Value,
+    current: &serde_json::Value,
+    changed: &mut Vec<String>,
+) {
+    match (new, current) {
+        (serde_json::Value::Object(new), serde_json::Value::Object(current)) => {
+            for (k, v) in new {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                match current.get(k) {
+                    Some(cur) => collect_changes(&key, v, cur, changed),
+                    None => changed.push(key),
+                }
+            }
+        }
+        _ => {
+            if new != current {
+                changed.push(prefix.to_string());
+            }
+        }
+    }
+}
+
+fn lookup<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+    key.split('.').try_fold(value, |v, k| v.get(k))
+}
@@ -0,0 +1,74 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+
+use opentelemetry::sdk::trace::IdGenerator;
+use opentelemetry::sdk::trace::RandomIdGenerator;
+use tracing::Span;
+
+use crate::ContextFields;
+
+/// Name of the field of the correlation id added by `correlation_fields`.
+const CORRELATION_FIELD: &str = "trace_id";
+
+tokio::task_local! {
+    /// The correlation id of the current task, see `with_correlation_id`.
+    static CORRELATION_ID: String;
+}
+
+/// Generate a random correlation id, a 32 chars hex string in the same
+/// shape as an OpenTelemetry trace id.
+///
+/// Use it to group the logs of a request that has no incoming trace context.
+pub fn new_correlation_id() -> String {
+    RandomIdGenerator::default().new_trace_id().to_string()
+}
+
+/// Create a `request` span carrying `correlation_id` as a field.
+///
+/// All events emitted inside the span, including in its child spans,
+/// are logged with the `correlation_id`.
+pub fn request_span(correlation_id: &str) -> Span {
+    tracing::info_span!("request", correlation_id = %correlation_id)
+}
+
+/// Run `f` with `correlation_id` as the correlation id of the current task,
+/// e.g. the one of the incoming request, without a tracer.
+///
+/// Spawned tasks do not inherit it, their futures have to be wrapped too.
+pub async fn with_correlation_id<F: Future>(correlation_id: String, f: F) -> F::Output {
+    CORRELATION_ID.scope(correlation_id, f).await
+}
+
+/// Call `f` with `correlation_id` as the correlation id of the current
+/// thread, e.g. in a job of a thread pool.
+pub fn with_correlation_id_sync<R>(correlation_id: String, f: impl FnOnce() -> R) -> R {
+    CORRELATION_ID.sync_scope(correlation_id, f)
+}
+
+/// The correlation id set by `with_correlation_id` for the current task, if any.
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Context fields adding the correlation id of the current task as `trace_id`,
+/// to set as `Config::context_fields`: log lines are correlated without
+/// installing a tracer or creating spans.
+pub fn correlation_fields() -> ContextFields {
+    ContextFields::new(|| match correlation_id() {
+        Some(id) => vec![(CORRELATION_FIELD.to_string(), id)],
+        None => vec![],
+    })
+}
@@ -0,0 +1,160 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::any::TypeId;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use tracing::level_filters::LevelFilter;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::subscriber::Interest;
+use tracing::Event;
+use tracing::Metadata;
+use tracing::Span;
+use tracing::Subscriber;
+use tracing_core::span::Current;
+use tracing_subscriber::filter::FilterId;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::DefaultSpan;
+
+/// The id and metadata of the default span, set once it is opened.
+static OPENED: OnceCell<(Id, &'static Metadata<'static>)> = OnceCell::new();
+/// The default span, kept open until `close`.
+static SPAN: Lazy<Mutex<Option<Span>>> = Lazy::new(|| Mutex::new(None));
+/// Set by `close`: the id of the default span may then be reused.
+static CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Open the default span built by `default_span`. Once per process: a span
+/// is only opened for the first subscriber installed.
+pub(crate) fn open(default_span: &DefaultSpan) {
+    let span = (default_span.0)();
+    if let (Some(id), Some(meta)) = (span.id(), span.metadata()) {
+        if OPENED.set((id, meta)).is_ok() {
+            *SPAN.lock().unwrap() = Some(span);
+        }
+    }
+}
+
+/// Close the default span, if open, so that the exporter sends it.
+pub(crate) fn close() {
+    CLOSED.store(true, Ordering::Release);
+    let span = SPAN.lock().unwrap().take();
+    drop(span);
+}
+
+/// A subscriber reporting the default span as the current span when no span
+/// is entered on the thread, so that such events are recorded in it.
+///
+/// The spans are created by `inner` as usual: new spans without explicit
+/// parent still see no current span and stay roots.
+pub(crate) struct WithDefaultSpan<S> {
+    inner: S,
+    on: bool,
+}
+
+impl<S> WithDefaultSpan<S> {
+    pub fn new(inner: S, on: bool) -> Self {
+        Self { inner, on }
+    }
+}
+
+impl<S> Subscriber for WithDefaultSpan<S>
+where S: Subscriber
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.inner.new_span(span)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        self.inner.record(span, values)
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.inner.record_follows_from(span, follows)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>) -> bool {
+        self.inner.event_enabled(event)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        self.inner.event(event)
+    }
+
+    fn enter(&self, span: &Id) {
+        self.inner.enter(span)
+    }
+
+    fn exit(&self, span: &Id) {
+        self.inner.exit(span)
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.inner.clone_span(id)
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        self.inner.try_close(id)
+    }
+
+    fn current_span(&self) -> Current {
+        let current = self.inner.current_span();
+        if !self.on || current.id().is_some() || CLOSED.load(Ordering::Acquire) {
+            return current;
+        }
+        match OPENED.get() {
+            Some((id, meta)) => Current::new(id.clone(), meta),
+            None => current,
+        }
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            return Some(self as *const Self as *const ());
+        }
+        self.inner.downcast_raw(id)
+    }
+}
+
+impl<'a, S> LookupSpan<'a> for WithDefaultSpan<S>
+where S: Subscriber + LookupSpan<'a>
+{
+    type Data = S::Data;
+
+    fn span_data(&'a self, id: &Id) -> Option<Self::Data> {
+        self.inner.span_data(id)
+    }
+
+    fn register_filter(&mut self) -> FilterId {
+        self.inner.register_filter()
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use tracing::warn;
+
+use crate::file_appender::rotated_files;
+use crate::DiskFullPolicy;
+use crate::INTERNAL_TARGET;
+
+/// Times the disk of a log dir was found full, see `disk_full_incidents`.
+static DISK_FULL_INCIDENTS: AtomicU64 = AtomicU64::new(0);
+
+/// How long lines are dropped before writing is tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Returns the number of times a log file write failed because the disk was
+/// full. An incident lasts until a write succeeds again.
+pub fn disk_full_incidents() -> u64 {
+    DISK_FULL_INCIDENTS.load(Ordering::Relaxed)
+}
+
+/// Wraps the appender of a log file, applying `policy` when writes fail
+/// because the disk is full. Other errors are returned as is.
+pub struct DiskFullWriter<W> {
+    inner: W,
+    dir: PathBuf,
+    prefix: String,
+    policy: DiskFullPolicy,
+    /// The last write failed because the disk was full.
+    full: bool,
+    /// Since when the lines are dropped, `None` while writing.
+    dropping_since: Option<Instant>,
+}
+
+impl<W: Write> DiskFullWriter<W> {
+    /// `dir` and `prefix` are the ones of `inner`, to find the rotated files.
+    pub fn new(inner: W, dir: &str, prefix: &str, policy: DiskFullPolicy) -> Self {
+        Self {
+            inner,
+            dir: PathBuf::from(dir),
+            prefix: prefix.to_string(),
+            policy,
+            full: false,
+            dropping_since: None,
+        }
+    }
+
+    /// Delete the oldest rotated file of the log or of its JSON sidecar, never
+    /// the current ones, the newest of each.
+    ///
+    /// Returns false if there is nothing to delete.
+    fn delete_oldest(&self) -> bool {
+        let sidecar = format!("{}.json", self.prefix);
+        // The names of both sort by time after the prefix.
+        let oldest = [&self.prefix, &sidecar]
+            .into_iter()
+            .filter_map(|prefix| {
+                let mut names = rotated_files(&self.dir, prefix);
+                if names.len() < 2 {
+                    return None;
+                }
+                let name = names.swap_remove(0);
+                Some((name[prefix.len()..].to_string(), name))
+            })
+            .min();
+        match oldest {
+            Some((_, name)) => fs::remove_file(self.dir.join(name)).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl<W: Write> Write for DiskFullWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(since) = self.dropping_since {
+            if since.elapsed() < RETRY_INTERVAL {
+                return Ok(buf.len());
+            }
+        }
+
+        let mut backoff = Duration::from_millis(100);
+        loop {
+            match self.inner.write(buf) {
+                Ok(n) => {
+                    self.full = false;
+                    self.dropping_since = None;
+                    return Ok(n);
+                }
+                Err(e) if is_disk_full(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            if !self.full {
+                self.full = true;
+                DISK_FULL_INCIDENTS.fetch_add(1, Ordering::Relaxed);
+                // Reported from another thread: this one writes the log files,
+                // it would wait for itself to write the event.
+                let (dir, policy) = (self.dir.clone(), self.policy);
+                thread::spawn(move || {
+                    warn!(
+                        target: INTERNAL_TARGET,
+                        "log dir {} is full, disk_full_policy={}",
+                        dir.display(),
+                        policy
+                    );
+                });
+            }
+
+            let retry = match self.policy {
+                DiskFullPolicy::StopLogging => false,
+                DiskFullPolicy::Block => {
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    true
+                }
+                DiskFullPolicy::DeleteOldest => self.delete_oldest(),
+            };
+            if !retry {
+                self.dropping_since = Some(Instant::now());
+                return Ok(buf.len());
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner.flush() {
+            Err(e) if is_disk_full(&e) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// `ENOSPC` on unix, `ERROR_HANDLE_DISK_FULL` or `ERROR_DISK_FULL` on windows.
+fn is_disk_full(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    const CODES: &[i32] = &[28];
+    #[cfg(windows)]
+    const CODES: &[i32] = &[39, 112];
+    #[cfg(not(any(unix, windows)))]
+    const CODES: &[i32] = &[];
+
+    matches!(e.raw_os_error(), Some(code) if CODES.contains(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_oldest_prunes_the_sidecar_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "app.2022-10-01",
+            "app.2022-10-03",
+            "app.json.2022-10-02",
+            "app.json.2022-10-03",
+            "app.spans.2022-09-30",
+        ] {
+            fs::write(dir.path().join(name), "line\n").unwrap();
+        }
+        let writer = DiskFullWriter::new(
+            io::sink(),
+            dir.path().to_str().unwrap(),
+            "app",
+            DiskFullPolicy::DeleteOldest,
+        );
+
+        assert!(writer.delete_oldest());
+        assert!(!dir.path().join("app.2022-10-01").exists());
+        assert!(writer.delete_oldest());
+        assert!(!dir.path().join("app.json.2022-10-02").exists());
+        // The current files, and the ones of other prefixes, are kept.
+        assert!(!writer.delete_oldest());
+        assert!(dir.path().join("app.2022-10-03").exists());
+        assert!(dir.path().join("app.json.2022-10-03").exists());
+        assert!(dir.path().join("app.spans.2022-09-30").exists());
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::Event;
+use tracing::Level;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::ErrorBufferConfig;
+
+thread_local! {
+    /// The buffer of the event being written on this thread, if it is held back.
+    static HELD: RefCell<Option<Arc<Buffer>>> = const { RefCell::new(None) };
+}
+
+/// The lines held back for a span and the spans inside it.
+struct Buffer {
+    /// `None` once flushed: the span failed, the following lines are written as is.
+    lines: Mutex<Option<Vec<u8>>>,
+    max_bytes: usize,
+}
+
+impl Buffer {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            lines: Mutex::new(Some(vec![])),
+            max_bytes,
+        }
+    }
+
+    /// Append `buf` to the lines, dropping the oldest ones beyond `max_bytes`.
+    /// Returns false if the lines were flushed, `buf` has to be written then.
+    fn hold(&self, buf: &[u8]) -> bool {
+        let mut lines = self.lines.lock().unwrap();
+        let lines = match lines.as_mut() {
+            Some(lines) => lines,
+            None => return false,
+        };
+
+        lines.extend_from_slice(buf);
+        if lines.len() > self.max_bytes {
+            // Drop whole lines, up to the first line end after the excess.
+            let excess = lines.len() - self.max_bytes;
+            let cut = lines[excess - 1..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(lines.len(), |i| excess + i);
+            lines.drain(..cut);
+        }
+        true
+    }
+
+    fn take(&self) -> Option<Vec<u8>> {
+        self.lines.lock().unwrap().take()
+    }
+}
+
+/// The buffer of a span, shared with the spans inside it.
+struct Scope(Arc<Buffer>);
+
+/// Holds back the verbose lines of the events inside the configured spans,
+/// e.g. the `DEBUG` lines of a `handle_request` span, written by an
+/// `ErrorBufferWriter` of the same file.
+///
+/// The first `ERROR` event inside the span, or inside any span within it,
+/// flushes the held lines to the file ahead of the error line, the following
+/// lines of the span are written as is. The lines of a span closed without
+/// error are discarded. A span within a buffering span shares its buffer,
+/// even if its name is configured too.
+///
+/// Must be stacked before the file layer, without its filter, so that errors
+/// are seen even if the file omits them.
+pub(crate) struct ErrorBufferLayer<W> {
+    span_names: Vec<String>,
+    level: Level,
+    max_bytes: usize,
+    writer: W,
+}
+
+impl<W> ErrorBufferLayer<W> {
+    /// `writer` is the one wrapped by the `ErrorBufferWriter`, the held lines
+    /// are flushed to it.
+    pub fn new(cfg: &ErrorBufferConfig, writer: W) -> Self {
+        Self {
+            span_names: cfg.span_names.clone(),
+            level: cfg.buffered_level(),
+            max_bytes: cfg.max_bytes,
+            writer,
+        }
+    }
+
+    fn set_held(buffer: Option<Arc<Buffer>>) {
+        HELD.with(|held| *held.borrow_mut() = buffer);
+    }
+}
+
+impl<S, W> Layer<S> for ErrorBufferLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // Span records, e.g. the Bunyan `START` ones, are never held back.
+        Self::set_held(None);
+
+        let span = match ctx.span(id) {
+            None => return,
+            Some(span) => span,
+        };
+        let inherited = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<Scope>().map(|s| s.0.clone()));
+        let buffer = match inherited {
+            Some(buffer) => buffer,
+            None if self.span_names.iter().any(|n| n == attrs.metadata().name()) => {
+                Arc::new(Buffer::new(self.max_bytes))
+            }
+            None => return,
+        };
+        span.extensions_mut().insert(Scope(buffer));
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let buffer = ctx
+            .event_span(event)
+            .and_then(|span| span.extensions().get::<Scope>().map(|s| s.0.clone()));
+        let buffer = match buffer {
+            None => return Self::set_held(None),
+            Some(buffer) => buffer,
+        };
+
+        let level = *event.metadata().level();
+        if level == Level::ERROR {
+            if let Some(lines) = buffer.take() {
+                let mut writer = self.writer.make_writer();
+                let _ = writer.write_all(&lines);
+                let _ = writer.flush();
+            }
+            Self::set_held(None);
+        } else if level >= self.level {
+            // `TRACE` is greater than `DEBUG`: more verbose levels compare greater.
+            Self::set_held(Some(buffer));
+        } else {
+            Self::set_held(None);
+        }
+    }
+
+    fn on_close(&self, _id: Id, _ctx: Context<'_, S>) {
+        // The buffer is dropped with the last span sharing it, e.g. the Bunyan
+        // `END` record of the span is written as is.
+        Self::set_held(None);
+    }
+}
+
+/// Wraps the writer of a file, holding back the lines selected by the
+/// `ErrorBufferLayer` seeing the event first.
+#[derive(Clone)]
+pub(crate) struct ErrorBufferWriter<W> {
+    inner: W,
+}
+
+impl<W> ErrorBufferWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for ErrorBufferWriter<W> {
+    type Writer = HeldWriter<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        HeldWriter {
+            inner: self.inner.make_writer(),
+            held: HELD.with(|held| held.borrow().clone()),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        HeldWriter {
+            inner: self.inner.make_writer_for(meta),
+            held: HELD.with(|held| held.borrow().clone()),
+        }
+    }
+}
+
+pub(crate) struct HeldWriter<W> {
+    inner: W,
+    held: Option<Arc<Buffer>>,
+}
+
+impl<W: Write> Write for HeldWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(buffer) = &self.held {
+            if buffer.hold(buf) {
+                return Ok(buf.len());
+            }
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
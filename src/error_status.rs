@@ -0,0 +1,215 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+
+use opentelemetry::trace::Status;
+use opentelemetry::Key;
+use opentelemetry::Value;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Name of the attribute added to the spans with an `ERROR` event.
+const ERROR_ATTRIBUTE: &str = "error";
+/// Name of the attribute with the most severe level of the events of a span.
+const MAX_EVENT_LEVEL_ATTRIBUTE: &str = "max_event_level";
+
+/// Adds the message of the first `ERROR` event emitted in a span as the message
+/// of its OpenTelemetry `Error` status, so that failed operations show up in
+/// red in jaeger with the reason. The `tracing_opentelemetry` layer already
+/// sets the status on such events, but without message.
+///
+/// With `with_level_attributes`, the spans also get a `max_event_level`
+/// attribute with the most severe level of their events, e.g. `WARN`, and
+/// `error=true` once an `ERROR` event is emitted, to search for the traces
+/// that logged warnings or errors. Only the events passing the filter of the
+/// exporter count, and the attributes must be in `TraceConfig::tag_allowlist`
+/// if it is set.
+///
+/// Only the span the event is emitted in is marked, not its parents. A span
+/// already marked `Ok`, e.g. by an `otel.status_code` field, or with a status
+/// message, e.g. from an `otel.status_message` field, is left as is.
+///
+/// It must be stacked after the `tracing_opentelemetry` layer.
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorStatusLayer {
+    status: bool,
+    level_attributes: bool,
+}
+
+impl ErrorStatusLayer {
+    /// Set the status only.
+    pub fn new() -> Self {
+        Self {
+            status: true,
+            level_attributes: false,
+        }
+    }
+
+    /// Set the status message of the spans with an `ERROR` event if `on`.
+    pub fn with_status(mut self, on: bool) -> Self {
+        self.status = on;
+        self
+    }
+
+    /// Add the `max_event_level` and `error` attributes if `on`.
+    pub fn with_level_attributes(mut self, on: bool) -> Self {
+        self.level_attributes = on;
+        self
+    }
+}
+
+impl Default for ErrorStatusLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The most severe level of the events of a span.
+struct MaxEventLevel(Level);
+
+impl<S> Layer<S> for ErrorStatusLayer
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level != Level::ERROR && !self.level_attributes {
+            return;
+        }
+        let span = match ctx.event_span(event) {
+            Some(span) => span,
+            None => return,
+        };
+
+        let mut extensions = span.extensions_mut();
+        // More severe levels compare less: `ERROR` is the least.
+        let raised = self.level_attributes
+            && match extensions.get_mut::<MaxEventLevel>() {
+                Some(max) => level < max.0,
+                None => true,
+            };
+        if raised {
+            extensions.replace(MaxEventLevel(level));
+        }
+
+        let builder = match extensions.get_mut::<OtelData>() {
+            Some(data) => &mut data.builder,
+            None => return,
+        };
+        if raised {
+            let attributes = builder.attributes.get_or_insert_with(Default::default);
+            attributes.insert(
+                Key::from_static_str(MAX_EVENT_LEVEL_ATTRIBUTE),
+                Value::from(level.as_str()),
+            );
+            if level == Level::ERROR {
+                attributes.insert(Key::from_static_str(ERROR_ATTRIBUTE), Value::Bool(true));
+            }
+        }
+
+        if !self.status || level != Level::ERROR {
+            return;
+        }
+        let described = match &builder.status {
+            Status::Ok => true,
+            Status::Error { description } => !description.is_empty(),
+            Status::Unset => false,
+        };
+        if !described {
+            let mut visitor = MessageVisitor(None);
+            event.record(&mut visitor);
+            builder.status = Status::error(visitor.0.unwrap_or_default());
+        }
+    }
+}
+
+struct MessageVisitor(Option<String>);
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::ready;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use opentelemetry::sdk::export::trace::ExportResult;
+    use opentelemetry::sdk::export::trace::SpanData;
+    use opentelemetry::sdk::export::trace::SpanExporter;
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing::error;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[derive(Clone, Debug, Default)]
+    struct Collect(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Collect {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn test_error_status_message() {
+        let spans = Collect::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(spans.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer("test"))
+                .and_then(ErrorStatusLayer::new()),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = info_span!("query").entered();
+            error!("table not found");
+            error!("second error");
+        });
+        // Waits for the exporter thread to export the ended spans.
+        drop(provider);
+
+        let spans = spans.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].status, Status::error("table not found"));
+    }
+}
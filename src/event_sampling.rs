@@ -0,0 +1,95 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::config::EventSamplingConfig;
+
+/// A layer samples events by the value of one of their fields, e.g. keeps
+/// only 1 in 100 of the events with `endpoint = "GET /health"`.
+///
+/// Events with other values, or without the field, are all kept.
+/// A dropped event is dropped for every layer, sampling is deterministic:
+/// the first one of every `n` events with a value is kept.
+///
+/// Field values are only known when an event is emitted, thus once this layer
+/// is installed every enabled callsite is checked, there is no max level fast path.
+pub struct EventSamplingLayer {
+    field: String,
+    /// `value -> (n, counter)`, keeps one in `n` events.
+    rates: HashMap<String, (u64, AtomicU64)>,
+}
+
+impl EventSamplingLayer {
+    pub fn new(cfg: &EventSamplingConfig) -> Self {
+        Self {
+            field: cfg.field.clone(),
+            rates: cfg
+                .one_in
+                .iter()
+                .map(|(value, n)| (value.clone(), (*n, AtomicU64::new(0))))
+                .collect(),
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventSamplingLayer {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        if event.metadata().fields().field(&self.field).is_none() {
+            return true;
+        }
+
+        let mut visitor = FieldVisitor {
+            field: &self.field,
+            value: None,
+        };
+        event.record(&mut visitor);
+
+        let rate = visitor.value.and_then(|value| self.rates.get(&value));
+        match rate {
+            None => true,
+            Some((0, _)) => false,
+            Some((n, counter)) => counter.fetch_add(1, Ordering::Relaxed) % n == 0,
+        }
+    }
+}
+
+struct FieldVisitor<'a> {
+    field: &'a str,
+    value: Option<String>,
+}
+
+impl<'a> Visit for FieldVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.field {
+            self.value = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == self.field {
+            self.value = Some(format!("{:?}", value));
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+use std::fmt;
+use std::time::SystemTime;
+
+use time::OffsetDateTime;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+
+thread_local! {
+    /// The time of the events emitted on this thread, `None` for the current time.
+    static EVENT_TIME: Cell<Option<SystemTime>> = const { Cell::new(None) };
+}
+
+/// Run `f` with `time` as the timestamp of the events it emits on the
+/// current thread, instead of the current time, e.g. to replay old records
+/// through the normal logging path:
+///
+/// `with_event_time(record.time, || info!(id = record.id, "replayed"))`
+///
+/// The text, logfmt and JSON lines of files and stderr are stamped with it,
+/// including with a `Config::timer`. Other outputs, e.g. spans or the query
+/// log, keep the current time. Do not `.await` in `f`, as for `SilenceGuard`.
+pub fn with_event_time<R>(time: SystemTime, f: impl FnOnce() -> R) -> R {
+    let _guard = EventTimeGuard {
+        previous: EVENT_TIME.with(|cell| cell.replace(Some(time))),
+    };
+    f()
+}
+
+/// Restores the previous time, even if `f` panics.
+struct EventTimeGuard {
+    previous: Option<SystemTime>,
+}
+
+impl Drop for EventTimeGuard {
+    fn drop(&mut self) {
+        EVENT_TIME.with(|cell| cell.set(self.previous));
+    }
+}
+
+/// The time set by `with_event_time` on the current thread.
+pub(crate) fn event_time() -> Option<SystemTime> {
+    EVENT_TIME.with(|cell| cell.get())
+}
+
+/// RFC3339 UTC, as the default timer, e.g. `2022-10-01T08:00:00.000000Z`.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let t = OffsetDateTime::from(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        t.year(),
+        u8::from(t.month()),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+        t.microsecond()
+    )
+}
+
+/// Writes the time set by `with_event_time`, or the time of `inner` if none.
+pub(crate) struct EventTimeOr<T>(pub T);
+
+impl<T: FormatTime> FormatTime for EventTimeOr<T> {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        match event_time() {
+            Some(time) => w.write_str(&rfc3339(time)),
+            None => self.0.format_time(w),
+        }
+    }
+}
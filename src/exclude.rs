@@ -0,0 +1,149 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::subscriber::Interest;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::layer::Filter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Marks a span excluded from the outputs, stored in the span extensions.
+struct Excluded;
+
+/// Spans and targets excluded from all outputs, e.g. the noisy `/health` handler.
+///
+/// - `filter` is a per-layer filter for every output layer: it disables the
+///   excluded targets, the excluded spans, and everything inside them.
+/// - `marker_layer` has to be added once, it marks the excluded spans so that
+///   the events inside them can be recognized.
+///
+/// Excluded callsites are disabled as early as possible, the events inside
+/// excluded spans are never formatted.
+#[derive(Clone, Debug, Default)]
+pub struct Exclusions {
+    span_names: Arc<Vec<String>>,
+    targets: Arc<Vec<String>>,
+}
+
+impl Exclusions {
+    pub fn new(span_names: &[String], targets: &[String]) -> Self {
+        Self {
+            span_names: Arc::new(span_names.to_vec()),
+            targets: Arc::new(targets.to_vec()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.span_names.is_empty() && self.targets.is_empty()
+    }
+
+    /// The per-layer filter hiding the excluded spans and events.
+    pub fn filter(&self) -> ExcludeFilter {
+        ExcludeFilter(self.clone())
+    }
+
+    /// The layer marking the excluded spans, `None` if no span is excluded.
+    pub fn marker_layer<S>(&self) -> Option<impl Layer<S>>
+    where S: Subscriber + for<'a> LookupSpan<'a> {
+        if self.span_names.is_empty() {
+            return None;
+        }
+        let exclusions = self.clone();
+        let filter = filter_fn(move |meta| meta.is_span() && exclusions.excludes_span_name(meta));
+        Some(ExcludedSpanMarker.with_filter(filter))
+    }
+
+    fn excludes_span_name(&self, meta: &Metadata<'_>) -> bool {
+        self.span_names.iter().any(|name| name == meta.name())
+    }
+
+    fn excludes_target(&self, meta: &Metadata<'_>) -> bool {
+        let target = meta.target();
+        self.targets
+            .iter()
+            .any(|prefix| match target.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+    }
+}
+
+/// See `Exclusions::filter`.
+#[derive(Clone, Debug)]
+pub struct ExcludeFilter(Exclusions);
+
+impl<S> Filter<S> for ExcludeFilter
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        let exclusions = &self.0;
+        if exclusions.is_empty() {
+            return true;
+        }
+        if exclusions.excludes_target(meta) {
+            return false;
+        }
+        if meta.is_span() && exclusions.excludes_span_name(meta) {
+            return false;
+        }
+        if exclusions.span_names.is_empty() {
+            return true;
+        }
+
+        match cx.lookup_current() {
+            None => true,
+            Some(span) => !span
+                .scope()
+                .any(|span| span.extensions().get::<Excluded>().is_some()),
+        }
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        let exclusions = &self.0;
+        if exclusions.is_empty() {
+            return Interest::always();
+        }
+        if exclusions.excludes_target(meta) {
+            return Interest::never();
+        }
+        if meta.is_span() && exclusions.excludes_span_name(meta) {
+            return Interest::never();
+        }
+        // Depends on the current span.
+        if exclusions.span_names.is_empty() {
+            Interest::always()
+        } else {
+            Interest::sometimes()
+        }
+    }
+}
+
+struct ExcludedSpanMarker;
+
+impl<S> Layer<S> for ExcludedSpanMarker
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().replace(Excluded);
+        }
+    }
+}
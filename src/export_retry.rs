@@ -0,0 +1,101 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use opentelemetry::sdk::export::trace::ExportResult;
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::export::trace::SpanExporter;
+use opentelemetry::trace::TraceError;
+
+/// Batches not exported after all retries.
+static EXPORT_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Returns the number of span batches dropped because exporting them failed,
+/// after `TraceConfig::export_max_retries` retries.
+pub fn trace_export_failures() -> u64 {
+    EXPORT_FAILURES.load(Ordering::Relaxed)
+}
+
+/// Wraps a span exporter, retrying failed exports with exponential backoff,
+/// from 100ms up to 5s between attempts.
+///
+/// Only transport errors and timeouts are retried. The retries run within the
+/// export timeout of the batch processor, 30s by default: a batch still
+/// failing then is dropped and counted too. With `max_retries = 0` failed
+/// batches are only counted.
+#[derive(Debug)]
+pub(crate) struct RetryExporter<E> {
+    inner: Arc<Mutex<E>>,
+    max_retries: usize,
+}
+
+impl<E> RetryExporter<E> {
+    pub(crate) fn new(inner: E, max_retries: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            max_retries,
+        }
+    }
+}
+
+impl<E: SpanExporter + 'static> SpanExporter for RetryExporter<E> {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let inner = self.inner.clone();
+        let max_retries = self.max_retries;
+
+        Box::pin(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            let mut retries = 0;
+            loop {
+                // Do not hold the lock while waiting for the export.
+                let export = inner.lock().unwrap().export(batch.clone());
+                match export.await {
+                    Ok(()) => return Ok(()),
+                    Err(e) if retries < max_retries && is_transient(&e) => {
+                        retries += 1;
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                    Err(e) => {
+                        EXPORT_FAILURES.fetch_add(1, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+            }
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.lock().unwrap().shutdown();
+    }
+}
+
+/// `TraceError::Other` are local errors, e.g. a span that can not be encoded,
+/// retrying does not help.
+fn is_transient(e: &TraceError) -> bool {
+    matches!(e, TraceError::ExportFailed(_) | TraceError::ExportTimedOut(_))
+}
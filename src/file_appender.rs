@@ -0,0 +1,254 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use time::OffsetDateTime;
+use time::UtcOffset;
+use tracing_appender::rolling::RollingFileAppender;
+
+use crate::sidecar::SidecarAppender;
+
+/// The appender of a log file, naming the rotated files in UTC or local time.
+#[derive(Debug)]
+pub enum FileAppender {
+    Utc(RollingFileAppender),
+    /// Naming the files in local time, or in UTC to cap their total bytes.
+    Local(LocalRollingAppender),
+    /// Writing a log and its JSON sidecar, rotated together.
+    Sidecar(SidecarAppender),
+}
+
+impl Write for FileAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileAppender::Utc(appender) => appender.write(buf),
+            FileAppender::Local(appender) => appender.write(buf),
+            FileAppender::Sidecar(appender) => appender.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileAppender::Utc(appender) => appender.flush(),
+            FileAppender::Local(appender) => appender.flush(),
+            FileAppender::Sidecar(appender) => appender.flush(),
+        }
+    }
+}
+
+/// How often a `LocalRollingAppender` starts a new file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocalRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+/// A rolling file appender naming the files in local time, e.g.
+/// `databend-query.2022-10-01-08` for the hour starting at 08:00 local time,
+/// with the same layout as `RollingFileAppender`.
+///
+/// The local offset is read once, when the appender is created:
+///
+/// - After a DST change, the names are off by an hour until a restart, e.g.
+///   after falling back, the lines of the repeated hour are appended to the
+///   file of the following hour. Daily files switch at 23:00 or 01:00.
+/// - On some unix platforms the offset can only be read while the process
+///   is single threaded, UTC is used otherwise. Initialize logging first
+///   thing in `main`.
+///
+/// `LocalRollingAppender::new_utc` names the files in UTC, as `RollingFileAppender`
+/// does, e.g. to cap the total bytes of the files, which it can not.
+#[derive(Debug)]
+pub struct LocalRollingAppender {
+    dir: PathBuf,
+    prefix: String,
+    rotation: LocalRotation,
+    offset: UtcOffset,
+    max_files: usize,
+    max_total_bytes: Option<u64>,
+    current: Option<(String, File)>,
+}
+
+impl LocalRollingAppender {
+    pub fn new(
+        dir: &str,
+        prefix: &str,
+        rotation: LocalRotation,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+        Self::with_offset(dir, prefix, rotation, max_files, offset)
+    }
+
+    pub fn new_utc(
+        dir: &str,
+        prefix: &str,
+        rotation: LocalRotation,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        Self::with_offset(dir, prefix, rotation, max_files, UtcOffset::UTC)
+    }
+
+    fn with_offset(
+        dir: &str,
+        prefix: &str,
+        rotation: LocalRotation,
+        max_files: usize,
+        offset: UtcOffset,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut appender = Self {
+            dir: PathBuf::from(dir),
+            prefix: prefix.to_string(),
+            rotation,
+            offset,
+            max_files,
+            max_total_bytes: None,
+            current: None,
+        };
+        appender.file()?;
+        Ok(appender)
+    }
+
+    /// Delete the oldest files on rotation until all the files of the log
+    /// take at most `max` bytes, besides `max_files`. `None` means no limit.
+    pub fn with_max_total_bytes(mut self, max: Option<u64>) -> Self {
+        self.max_total_bytes = max;
+        self.prune();
+        self
+    }
+
+    fn file_name(&self, now: OffsetDateTime) -> String {
+        let now = now.to_offset(self.offset);
+        let (y, m, d) = (now.year(), u8::from(now.month()), now.day());
+        match self.rotation {
+            LocalRotation::Minutely => format!(
+                "{}.{:04}-{:02}-{:02}-{:02}-{:02}",
+                self.prefix,
+                y,
+                m,
+                d,
+                now.hour(),
+                now.minute()
+            ),
+            LocalRotation::Hourly => {
+                format!("{}.{:04}-{:02}-{:02}-{:02}", self.prefix, y, m, d, now.hour())
+            }
+            LocalRotation::Daily => format!("{}.{:04}-{:02}-{:02}", self.prefix, y, m, d),
+            LocalRotation::Never => self.prefix.clone(),
+        }
+    }
+
+    /// Write `buf` to the file of the period of `time` instead of the current one,
+    /// e.g. the one of the log line of an event, see `SidecarAppender`.
+    pub(crate) fn write_at(&mut self, time: OffsetDateTime, buf: &[u8]) -> io::Result<()> {
+        self.file_at(time)?.write_all(buf)
+    }
+
+    /// The file of the current period, opened on the first write of a period.
+    fn file(&mut self) -> io::Result<&mut File> {
+        self.file_at(OffsetDateTime::now_utc())
+    }
+
+    fn file_at(&mut self, time: OffsetDateTime) -> io::Result<&mut File> {
+        let name = self.file_name(time);
+        let rotated = match &self.current {
+            Some((current, _)) => *current != name,
+            None => true,
+        };
+        if rotated {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(&name))?;
+            self.current = Some((name, file));
+            self.prune();
+        }
+        Ok(&mut self.current.as_mut().unwrap().1)
+    }
+
+    /// Delete the oldest files beyond `max_files` or `max_total_bytes`,
+    /// never the current one, the newest.
+    fn prune(&self) {
+        if self.rotation == LocalRotation::Never
+            || (self.max_files == 0 && self.max_total_bytes.is_none())
+        {
+            return;
+        }
+
+        let names = rotated_files(&self.dir, &self.prefix);
+        let mut excess = match self.max_files {
+            0 => 0,
+            max_files => names.len().saturating_sub(max_files),
+        };
+        if let Some(max) = self.max_total_bytes {
+            let sizes = names
+                .iter()
+                .map(|name| fs::metadata(self.dir.join(name)).map_or(0, |m| m.len()))
+                .collect::<Vec<_>>();
+            let mut total = sizes[excess..].iter().sum::<u64>();
+            while total > max && excess + 1 < names.len() {
+                total -= sizes[excess];
+                excess += 1;
+            }
+        }
+
+        for name in &names[..excess] {
+            let _ = fs::remove_file(self.dir.join(name));
+        }
+    }
+}
+
+/// Names of the rotated files of `prefix` in `dir`, oldest first: names sort by time.
+///
+/// Both `RollingFileAppender` and `LocalRollingAppender` name them `<prefix>.<date>`.
+pub(crate) fn rotated_files(dir: &Path, prefix: &str) -> Vec<String> {
+    let prefix = format!("{}.", prefix);
+    let mut names = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            // Skip other files with the same prefix, e.g. `<prefix>.spans.*`.
+            .filter(|name| match name.strip_prefix(&prefix) {
+                Some(suffix) => suffix.starts_with(|c: char| c.is_ascii_digit()),
+                None => false,
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => return vec![],
+    };
+    names.sort();
+    names
+}
+
+impl Write for LocalRollingAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormatEvent;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::baggage::baggage_value;
+use crate::limited_fields::FIELDS_TRUNCATED;
+use crate::logfmt;
+use crate::source_location;
+use crate::source_location::Location;
+use crate::span_path;
+use crate::span_path::SPAN_PATH_FIELD;
+use crate::Config;
+use crate::ContextFields;
+use crate::SourceLocation;
+
+/// Wraps an event formatter and adds constant fields, e.g. `cluster` and
+/// `region`, to every line, followed by the line number `seq` if
+/// `Config::seq_field` is set, the milliseconds since init `uptime_ms` if
+/// `Config::uptime_field` is set, the configured baggage values, e.g.
+/// `tenant`, found in the current context, and the `Config::context_fields`.
+///
+/// For text and logfmt lines the fields are written in front of the line as
+/// `key=value` pairs, escaped and followed by the delimiter of logfmt lines
+/// set by `with_logfmt_delimiter`. JSON lines are parsed and the fields are added as members,
+/// the event `fields` are capped by `Config::max_fields_per_event` and the
+/// location members set by `Config::json_source_location` and the
+/// `span_path` of `Config::span_path_field` then.
+pub struct GlobalFields<F> {
+    inner: F,
+    fields: Arc<Vec<(String, String)>>,
+    baggage_keys: Arc<Vec<String>>,
+    seq: Option<AtomicU64>,
+    started: Option<Instant>,
+    context_fields: Option<ContextFields>,
+    max_fields: Option<usize>,
+    location: SourceLocation,
+    span_path: bool,
+    json: bool,
+    logfmt_delimiter: Option<char>,
+}
+
+impl<F> GlobalFields<F> {
+    pub fn new(inner: F, cfg: &Config, json: bool) -> Self {
+        Self {
+            inner,
+            fields: Arc::new(cfg.global_fields.clone()),
+            baggage_keys: Arc::new(cfg.trace.baggage_log_fields.clone()),
+            seq: if cfg.seq_field {
+                Some(AtomicU64::new(0))
+            } else {
+                None
+            },
+            started: if cfg.uptime_field {
+                Some(Instant::now())
+            } else {
+                None
+            },
+            context_fields: cfg.context_fields.clone(),
+            max_fields: if json { cfg.max_fields_per_event } else { None },
+            location: if json {
+                cfg.json_source_location
+            } else {
+                SourceLocation::Auto
+            },
+            span_path: json && cfg.span_path_field,
+            json,
+            logfmt_delimiter: None,
+        }
+    }
+
+    /// Write the fields as the logfmt pairs separated by `delimiter`.
+    pub fn with_logfmt_delimiter(mut self, delimiter: char) -> Self {
+        self.logfmt_delimiter = Some(delimiter);
+        self
+    }
+
+    fn baggage_fields(&self) -> Vec<(String, String)> {
+        self.baggage_keys
+            .iter()
+            .filter_map(|k| baggage_value(k).map(|v| (k.clone(), v)))
+            .collect()
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for GlobalFields<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if self.fields.is_empty()
+            && self.baggage_keys.is_empty()
+            && self.seq.is_none()
+            && self.started.is_none()
+            && self.context_fields.is_none()
+            && self.max_fields.is_none()
+            && self.location == SourceLocation::Auto
+            && !self.span_path
+        {
+            return self.inner.format_event(ctx, writer, event);
+        }
+
+        let mut extra = vec![];
+        if let Some(seq) = &self.seq {
+            let seq = seq.fetch_add(1, Ordering::Relaxed);
+            extra.push(("seq".to_string(), seq.to_string()));
+        }
+        if let Some(started) = &self.started {
+            let uptime = started.elapsed().as_millis();
+            extra.push(("uptime_ms".to_string(), uptime.to_string()));
+        }
+        extra.extend(self.baggage_fields());
+        if let Some(context_fields) = &self.context_fields {
+            extra.extend((context_fields.0)());
+        }
+        let fields = self.fields.iter().chain(extra.iter());
+
+        if let Some(d) = self.logfmt_delimiter {
+            let mut pairs = String::new();
+            for (k, v) in fields {
+                pairs.push_str(k);
+                pairs.push('=');
+                logfmt::write_value(&mut pairs, v, d);
+                pairs.push(d);
+            }
+            writer.write_str(&pairs)?;
+            return self.inner.format_event(ctx, writer, event);
+        }
+        if !self.json {
+            for (k, v) in fields {
+                write!(writer, "{}={} ", k, v)?;
+            }
+            return self.inner.format_event(ctx, writer, event);
+        }
+
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+
+        match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&line) {
+            Ok(mut record) => {
+                if let Some(max) = self.max_fields {
+                    if let Some(serde_json::Value::Object(event_fields)) =
+                        record.get_mut("fields")
+                    {
+                        truncate_fields(event_fields, max);
+                    }
+                }
+                let location = match event.normalized_metadata() {
+                    Some(meta) => Location::of(&meta),
+                    None => Location::of(event.metadata()),
+                };
+                source_location::apply(&mut record, self.location, &location);
+                if let Some(scope) = self.span_path.then(|| ctx.event_scope()).flatten() {
+                    let path = serde_json::Value::from(span_path::join(scope));
+                    record.insert(SPAN_PATH_FIELD.to_string(), path);
+                }
+                for (k, v) in fields {
+                    record.insert(k.clone(), serde_json::Value::from(v.as_str()));
+                }
+                let json = serde_json::to_string(&record).map_err(|_| fmt::Error)?;
+                writeln!(writer, "{}", json)
+            }
+            Err(_) => writer.write_str(&line),
+        }
+    }
+}
+
+/// Keep the `message` and at most `max` other fields, marking the truncation
+/// with `fields_truncated`.
+fn truncate_fields(fields: &mut serde_json::Map<String, serde_json::Value>, max: usize) {
+    let others = fields.keys().filter(|k| *k != "message").count();
+    if others <= max {
+        return;
+    }
+
+    let mut kept = 0;
+    fields.retain(|k, _| {
+        if k == "message" {
+            return true;
+        }
+        kept += 1;
+        kept <= max
+    });
+    fields.insert(FIELDS_TRUNCATED.to_string(), serde_json::Value::from(true));
+}
@@ -0,0 +1,153 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Level;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::filter::FilterFn;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Target of the events re-emitted at the downgraded level.
+pub const DOWNGRADED_TARGET: &str = "common_tracing::downgraded";
+
+/// Downgrades the level of events from some targets, e.g. `ERROR` to `WARN`,
+/// so that benign errors of a dependency do not fire alerts.
+///
+/// The level of an event is static metadata and can not be changed in place.
+/// Instead the original events are hidden from the output layers by
+/// [`TargetLevelOverrides::suppress_filter`], and this layer re-emits them at
+/// the downgraded level with:
+///
+/// - target set to [`DOWNGRADED_TARGET`], because targets of dynamically
+///   emitted events must be static too;
+/// - the original target and level in `original_target` and `original_level`;
+/// - all the original fields rendered into the message.
+///
+/// Re-emitting only works with the global default subscriber, scoped
+/// subscribers drop events dispatched from within a layer.
+/// Only downgrading is supported, an override to a more severe level is ignored.
+#[derive(Clone)]
+pub struct TargetLevelOverrides {
+    overrides: Arc<Vec<(String, Level)>>,
+}
+
+impl TargetLevelOverrides {
+    pub fn new(overrides: &[(String, Level)]) -> Self {
+        Self {
+            overrides: Arc::new(overrides.to_vec()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty()
+    }
+
+    /// Returns the downgraded level for an event, if its target matches an override.
+    ///
+    /// A target matches itself and its sub modules, `foo` matches `foo::bar` but not `foobar`.
+    fn downgraded(&self, meta: &Metadata<'_>) -> Option<Level> {
+        if !meta.is_event() {
+            return None;
+        }
+
+        let target = meta.target();
+        self.overrides
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || (target.starts_with(prefix.as_str())
+                        && target[prefix.len()..].starts_with("::"))
+            })
+            // More verbose levels are greater in tracing.
+            .filter(|(_, level)| level > meta.level())
+            .map(|(_, level)| *level)
+    }
+
+    /// A per-layer filter hiding the original events that are re-emitted by this layer.
+    pub fn suppress_filter(&self) -> FilterFn<impl Fn(&Metadata<'_>) -> bool> {
+        let overrides = self.clone();
+        filter_fn(move |meta| overrides.downgraded(meta).is_none())
+    }
+
+    /// A per-layer filter for this layer itself, so that it only sees the events to downgrade.
+    pub fn layer_filter(&self) -> FilterFn<impl Fn(&Metadata<'_>) -> bool> {
+        let overrides = self.clone();
+        filter_fn(move |meta| overrides.downgraded(meta).is_some())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for TargetLevelOverrides {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let level = match self.downgraded(meta) {
+            None => return,
+            Some(level) => level,
+        };
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let message = visitor.message;
+        let original_target = meta.target();
+        let original_level = meta.level().as_str();
+
+        macro_rules! re_emit {
+            ($level:expr) => {
+                tracing::event!(
+                    target: DOWNGRADED_TARGET,
+                    $level,
+                    original_target,
+                    original_level,
+                    "{}",
+                    message
+                )
+            };
+        }
+
+        match level {
+            Level::WARN => re_emit!(Level::WARN),
+            Level::INFO => re_emit!(Level::INFO),
+            Level::DEBUG => re_emit!(Level::DEBUG),
+            _ => re_emit!(Level::TRACE),
+        }
+    }
+}
+
+/// Renders all fields of an event into one message: `<message> k1=v1 k2=v2`.
+#[derive(Default)]
+pub(crate) struct MessageVisitor {
+    pub(crate) message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if !self.message.is_empty() {
+            self.message.push(' ');
+        }
+
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.message, "{}={:?}", field.name(), value);
+        }
+    }
+}
@@ -14,24 +14,189 @@
 
 #![deny(unused_crate_dependencies)]
 
+// Used by the benches only.
+#[cfg(test)]
+use criterion as _;
+
+pub use active_spans::dump_active_spans;
+pub use active_spans::ActiveSpanInfo;
+pub use active_spans::ActiveSpansLayer;
+pub use baggage::baggage_value;
+pub use baggage::set_baggage;
+pub use bootstrap::init_bootstrap_logger;
+pub use bootstrap::BootstrapGuard;
+pub use broadcast::subscribe_events;
+pub use broadcast::BroadcastEvent;
+pub use broadcast::BroadcastLayer;
+pub use buffer_guard::buffered_log_bytes;
+pub use buffer_guard::dropped_log_lines;
+pub use config::AnsiColor;
+pub use config::AnsiTheme;
+pub use config::BoxedLayer;
+pub use config::BroadcastConfig;
 pub use config::Config;
+pub use config::ContextFields;
+pub use config::CustomSampler;
+pub use config::CustomTimer;
+pub use config::DefaultSpan;
+pub use config::Destination;
+pub use config::DiskFullPolicy;
+pub use config::ErrorBufferConfig;
+pub use config::EventSamplingConfig;
+pub use config::ExtraLayers;
+pub use config::FieldFormatFn;
+pub use config::FieldFormatter;
 pub use config::FileConfig;
+pub use config::FileSinkConfig;
+pub use config::InvalidLogLevel;
+pub use config::LevelRoute;
+pub use config::LevelStyle;
+pub use config::LineEnding;
+pub use config::LogFormat;
+pub use config::LogLevel;
+pub use config::LOG_DIR_ENV;
+pub use config::OtlpLogsConfig;
+pub use config::Propagator;
+pub use config::SentryConfig;
+pub use config::SourceLocation;
 pub use config::StderrConfig;
+pub use config::TraceConfig;
+pub use config::WebhookConfig;
+#[cfg(feature = "config-watch")]
+pub use config_watch::watch_config_file;
+pub use correlation::correlation_fields;
+pub use correlation::correlation_id;
+pub use correlation::new_correlation_id;
+pub use correlation::request_span;
+pub use correlation::with_correlation_id;
+pub use correlation::with_correlation_id_sync;
+pub use disk_full::disk_full_incidents;
+pub use error_status::ErrorStatusLayer;
+pub use event_time::with_event_time;
+pub use export_retry::trace_export_failures;
+pub use level_override::TargetLevelOverrides;
+pub use level_override::DOWNGRADED_TARGET;
+pub use limited_fields::LimitedFields;
+pub use limited_fields::FIELDS_TRUNCATED;
+pub use logfmt::Logfmt;
+#[cfg(feature = "metrics")]
+pub use log_metrics::LogMetricsLayer;
+#[cfg(feature = "metrics")]
+pub use log_metrics::LOG_EVENTS_COUNTER;
+pub use logging::build_global_subscriber;
 pub use logging::init_logging;
+pub use logging::init_logging_with_outcome;
 pub use logging::init_query_logger;
+pub use logging::init_query_logger_with_config;
+pub use logging::InitOutcome;
+pub use logging::LayersEnabled;
 pub use logging::QueryLogger;
+pub use logging::INTERNAL_TARGET;
+pub use panic_hook::install_crash_log;
+pub use panic_hook::install_panic_logger;
 pub use panic_hook::log_panic;
 pub use panic_hook::set_panic_hook;
+pub use panic_hook::CRASH_LOG_FILE;
+pub use reload::ReloadHandle;
+#[cfg(feature = "resource-detection")]
+pub use resource::K8sResourceDetector;
+pub use signal_flush::flush_on_termination;
+pub use signal_flush::try_flush_from_signal;
+pub use silence::with_log_level_scope;
+pub use silence::SilenceGuard;
+#[cfg(feature = "slog")]
+pub use slog_bridge::SlogDrain;
+#[cfg(feature = "slog")]
+pub use slog_bridge::SLOG_TARGET;
+pub use span_events::SpanEventsLayer;
+pub use span_level::SpanLevelFilter;
+pub use span_timing::SpanTimingLayer;
+pub use span_timing::SPAN_TIMING_TARGET;
+pub use summary::shutdown_logging;
+pub use terminal::is_terminal_stderr;
+pub use terminal::is_terminal_stdout;
+pub use test_support::count_errors;
+pub use test_support::init_buffer_capture;
+pub use test_support::init_test_logging;
+pub use test_support::ErrorCounts;
+pub use test_support::FixedTime;
+pub use test_support::SharedBuffer;
+pub use test_support::TEST_LOG_ENV;
+pub use themed_text::SpanIdRadix;
+pub use themed_text::ThemedText;
+pub use tracer::flush_traces;
+pub use tracer::FlushTimeout;
 pub use tracing_to_jaeger::extract_remote_span_as_parent;
+pub use tracing_to_jaeger::extract_remote_span_context;
 pub use tracing_to_jaeger::inject_span_to_tonic_request;
+pub use tracing_to_jaeger::with_links;
+#[cfg(any(feature = "http-sink", feature = "otlp-logs"))]
+pub use remote_sinks::RemoteSinkError;
+#[cfg(feature = "http-sink")]
+pub use webhook::WebhookLayer;
 
 #[macro_use]
 mod macros;
+mod active_spans;
+mod baggage;
+mod bootstrap;
+mod broadcast;
+mod buffer_guard;
+mod bunyan_post;
 mod config;
+#[cfg(feature = "config-watch")]
+mod config_watch;
+mod correlation;
+mod default_span;
+mod disk_full;
+mod error_buffer;
+mod error_status;
+mod event_sampling;
+mod event_time;
+mod exclude;
+mod export_retry;
+mod file_appender;
+mod global_fields;
+mod level_override;
+mod limited_fields;
+mod line_ending;
+mod logfmt;
+#[cfg(feature = "metrics")]
+mod log_metrics;
 mod logging;
+mod otlp_json_file;
+#[cfg(feature = "otlp-logs")]
+mod otlp_logs;
 mod panic_hook;
+mod pipe_writer;
+mod reload;
+mod remote_sinks;
+#[cfg(feature = "resource-detection")]
+mod resource;
+mod sampled_filter;
+mod sentry_mapper;
+mod sidecar;
+mod signal_flush;
+mod silence;
+#[cfg(feature = "slog")]
+mod slog_bridge;
+mod source_location;
+mod span_attribute_filter;
+mod span_events;
+mod span_level;
+mod span_path;
+mod span_timing;
+mod summary;
+mod terminal;
+mod test_support;
+mod themed_text;
+mod tracer;
 mod tracing_to_jaeger;
+#[cfg(feature = "http-sink")]
+mod webhook;
 mod singleton_instance;
+#[cfg(all(windows, feature = "winlog"))]
+mod winlog;
 
 #[macro_export]
 macro_rules! func_name {
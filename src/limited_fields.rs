@@ -0,0 +1,213 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::fmt::Debug;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::DefaultFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::FormatFields;
+
+use crate::FieldFormatter;
+
+/// The field recorded when fields are dropped by `Config::max_fields_per_event`.
+pub const FIELDS_TRUNCATED: &str = "fields_truncated";
+
+/// Formats fields as `DefaultFields` does, keeping at most `max` of them
+/// besides the message, followed by `fields_truncated=true` if some were dropped.
+///
+/// Spans are formatted with it too, their fields are capped the same way.
+/// Use `LimitedFields::with_formatter` to render the fields with a `FieldFormatter`.
+#[derive(Clone, Debug, Default)]
+pub struct LimitedFields {
+    max: Option<usize>,
+    formatter: Option<FieldFormatter>,
+}
+
+impl LimitedFields {
+    /// `None` formats all fields.
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            formatter: None,
+        }
+    }
+
+    /// Render every kept field with `formatter`, `None` formats them as `DefaultFields`.
+    pub fn with_formatter(mut self, formatter: Option<FieldFormatter>) -> Self {
+        self.formatter = formatter;
+        self
+    }
+}
+
+impl<'w> FormatFields<'w> for LimitedFields {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'w>, fields: R) -> fmt::Result {
+        if self.max.is_none() && self.formatter.is_none() {
+            return DefaultFields::new().format_fields(writer, fields);
+        }
+
+        let mut visitor = LimitedVisitor {
+            line: String::new(),
+            max: self.max.unwrap_or(usize::MAX),
+            recorded: 0,
+            truncated: false,
+            formatter: self.formatter.as_ref(),
+            result: Ok(()),
+        };
+        fields.record(&mut visitor);
+        visitor.result?;
+        if visitor.truncated {
+            visitor.push(&format!("{}=true", FIELDS_TRUNCATED));
+        }
+        writer.write_str(&visitor.line)
+    }
+}
+
+struct LimitedVisitor<'a> {
+    line: String,
+    max: usize,
+    recorded: usize,
+    truncated: bool,
+    formatter: Option<&'a FieldFormatter>,
+    /// The first error of the formatter.
+    result: fmt::Result,
+}
+
+impl<'a> LimitedVisitor<'a> {
+    fn push(&mut self, s: &str) {
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        self.line.push_str(s);
+    }
+
+    /// Render a field with the formatter, dropping the separator if it wrote nothing.
+    fn push_formatted(&mut self, formatter: &FieldFormatter, field: &Field, value: &dyn Debug) {
+        let start = self.line.len();
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        let separated = self.line.len();
+
+        let result = (formatter.0)(&mut Writer::new(&mut self.line), field, value);
+        if self.line.len() == separated {
+            self.line.truncate(start);
+        }
+        if self.result.is_ok() {
+            self.result = result;
+        }
+    }
+}
+
+impl<'a> Visit for LimitedVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.record_debug(field, &format_args!("{}", value))
+        } else {
+            self.record_debug(field, &value)
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let name = field.name();
+        // Same as `DefaultFields`: `log` metadata is skipped, raw identifiers unescaped.
+        if name.starts_with("log.") {
+            return;
+        }
+
+        if name != "message" {
+            if self.recorded == self.max {
+                self.truncated = true;
+                return;
+            }
+            self.recorded += 1;
+        }
+
+        if let Some(formatter) = self.formatter {
+            self.push_formatted(formatter, field, value);
+        } else if name == "message" {
+            self.push(&format!("{:?}", value));
+        } else {
+            let name = name.strip_prefix("r#").unwrap_or(name);
+            self.push(&format!("{}={:?}", name, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use tracing::info;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn format_line(fields: LimitedFields) -> String {
+        let buf = Buffer::default();
+        let writer = buf.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .without_time()
+            .with_level(false)
+            .with_target(false)
+            .fmt_fields(fields)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            info!(user = "alice", password = "secret", internal = 1, "login");
+        });
+        let line = buf.0.lock().unwrap().clone();
+        String::from_utf8(line).unwrap()
+    }
+
+    fn redact() -> FieldFormatter {
+        FieldFormatter::new(|w, field, value| match field.name() {
+            "password" => write!(w, "password=***"),
+            "message" => write!(w, "{:?}", value),
+            "internal" => Ok(()),
+            name => write!(w, "{}={:?}", name, value),
+        })
+    }
+
+    #[test]
+    fn test_field_formatter() {
+        let fields = LimitedFields::new(None).with_formatter(Some(redact()));
+        assert_eq!(format_line(fields), "login user=\"alice\" password=***\n");
+
+        // Only the kept fields are rendered.
+        let fields = LimitedFields::new(Some(2)).with_formatter(Some(redact()));
+        assert_eq!(
+            format_line(fields),
+            "login user=\"alice\" password=*** fields_truncated=true\n"
+        );
+    }
+}
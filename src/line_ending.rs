@@ -0,0 +1,81 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::Write;
+
+use tracing::Metadata;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::LineEnding;
+
+/// Wraps a writer, ending the lines with `\r\n` instead of `\n` for
+/// `LineEnding::Crlf`. Lines already ending with `\r\n` are kept as is.
+///
+/// Newlines inside a line, e.g. in a multi-line message, are converted too.
+#[derive(Clone, Debug)]
+pub(crate) struct LineEndingWriter<W> {
+    inner: W,
+    crlf: bool,
+}
+
+impl<W> LineEndingWriter<W> {
+    pub fn new(inner: W, line_ending: LineEnding) -> Self {
+        Self {
+            inner,
+            crlf: line_ending == LineEnding::Crlf,
+        }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for LineEndingWriter<W> {
+    type Writer = LineEndingWriter<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LineEndingWriter {
+            inner: self.inner.make_writer(),
+            crlf: self.crlf,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        LineEndingWriter {
+            inner: self.inner.make_writer_for(meta),
+            crlf: self.crlf,
+        }
+    }
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.crlf || !buf.contains(&b'\n') {
+            return self.inner.write(buf);
+        }
+
+        let mut converted = Vec::with_capacity(buf.len() + 2);
+        for (i, b) in buf.iter().enumerate() {
+            if *b == b'\n' && (i == 0 || buf[i - 1] != b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(*b);
+        }
+        // The line is written at once, as by the formatters.
+        self.inner.write_all(&converted)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
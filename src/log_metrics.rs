@@ -0,0 +1,33 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Name of the counter of emitted events, labeled by `level`.
+pub const LOG_EVENTS_COUNTER: &str = "log_events";
+
+/// A layer counts every event with the `metrics` crate.
+///
+/// The counter is reported to whatever `metrics` recorder the application
+/// installs, e.g. a Prometheus exporter. Without a recorder it is a no-op.
+pub struct LogMetricsLayer;
+
+impl<S: Subscriber> Layer<S> for LogMetricsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        metrics::increment_counter!(LOG_EVENTS_COUNTER, "level" => event.metadata().level().as_str());
+    }
+}
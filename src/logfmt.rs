@@ -0,0 +1,237 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Write;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::time::SystemTime;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormatEvent;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::limited_fields::FIELDS_TRUNCATED;
+use crate::LevelStyle;
+
+/// Formats events as logfmt lines:
+///
+/// `ts=2022-10-01T08:00:00.000000Z level=INFO target=foo::bar spans=req:query msg="hello world" rows=3`
+///
+/// Values containing spaces, `=`, `"`, control characters or the delimiter
+/// are quoted and escaped, an empty value is written as `""`.
+///
+/// `ts` is written by the system clock, use `Logfmt::with_timer` to replace it.
+/// `level` is written in full, use `Logfmt::with_level_style` to shorten it.
+/// Use `Logfmt::with_max_fields` to cap the number of event fields, and
+/// `Logfmt::with_delimiter` to separate them with e.g. a tab.
+#[derive(Clone, Copy, Debug)]
+pub struct Logfmt<T = SystemTime> {
+    timer: T,
+    level_style: LevelStyle,
+    max_fields: Option<usize>,
+    delimiter: char,
+}
+
+impl<T: Default> Default for Logfmt<T> {
+    fn default() -> Self {
+        Self {
+            timer: T::default(),
+            level_style: LevelStyle::default(),
+            max_fields: None,
+            delimiter: ' ',
+        }
+    }
+}
+
+impl Logfmt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T> Logfmt<T> {
+    pub fn with_timer<T2: FormatTime>(self, timer: T2) -> Logfmt<T2> {
+        Logfmt {
+            timer,
+            level_style: self.level_style,
+            max_fields: self.max_fields,
+            delimiter: self.delimiter,
+        }
+    }
+
+    pub fn with_level_style(mut self, level_style: LevelStyle) -> Self {
+        self.level_style = level_style;
+        self
+    }
+
+    /// Keep at most `max` event fields besides `msg`, followed by
+    /// `fields_truncated=true` if some were dropped. `None` keeps all fields.
+    pub fn with_max_fields(mut self, max: Option<usize>) -> Self {
+        self.max_fields = max;
+        self
+    }
+
+    /// Separate the pairs with `delimiter` instead of a space, e.g. `'\t'`
+    /// for parsers splitting on tabs. It never appears unescaped in a value:
+    /// a tab is written as `\t`, another delimiter as a `\u{..}` escape.
+    ///
+    /// It must not be `=`, `"`, `\`, a letter or a digit.
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+}
+
+impl<S, N, T> FormatEvent<S, N> for Logfmt<T>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    T: FormatTime,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+
+        let d = self.delimiter;
+        let mut ts = String::new();
+        self.timer.format_time(&mut Writer::new(&mut ts))?;
+
+        let mut line = String::from("ts=");
+        write_value(&mut line, &ts, d);
+        write_pair(&mut line, d, "level", self.level_style.render(meta.level()));
+        write_pair(&mut line, d, "target", meta.target());
+
+        if let Some(scope) = ctx.event_scope() {
+            let spans = scope
+                .from_root()
+                .map(|span| span.name())
+                .collect::<Vec<_>>()
+                .join(":");
+            write_pair(&mut line, d, "spans", &spans);
+        }
+
+        let mut visitor = LogfmtVisitor {
+            line: &mut line,
+            delimiter: d,
+            remaining: self.max_fields,
+            truncated: false,
+        };
+        event.record(&mut visitor);
+        if visitor.truncated {
+            write_pair(&mut line, d, FIELDS_TRUNCATED, "true");
+        }
+
+        writeln!(writer, "{}", line)
+    }
+}
+
+struct LogfmtVisitor<'a> {
+    line: &'a mut String,
+    delimiter: char,
+    /// Number of fields still allowed besides the message, `None` if unlimited.
+    remaining: Option<usize>,
+    truncated: bool,
+}
+
+impl<'a> LogfmtVisitor<'a> {
+    /// Whether `field` is kept, accounting for it.
+    fn keep(&mut self, field: &Field) -> bool {
+        match &mut self.remaining {
+            _ if field.name() == "message" => true,
+            None => true,
+            Some(0) => {
+                self.truncated = true;
+                false
+            }
+            Some(n) => {
+                *n -= 1;
+                true
+            }
+        }
+    }
+}
+
+impl<'a> Visit for LogfmtVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.keep(field) {
+            write_pair(self.line, self.delimiter, key(field), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if self.keep(field) {
+            write_pair(
+                self.line,
+                self.delimiter,
+                key(field),
+                &format!("{:?}", value),
+            );
+        }
+    }
+}
+
+fn key(field: &Field) -> &'static str {
+    match field.name() {
+        "message" => "msg",
+        name => name,
+    }
+}
+
+/// Append `<delimiter>key=value` to `line`, quoting the value when required.
+fn write_pair(line: &mut String, delimiter: char, key: &str, value: &str) {
+    let _ = write!(line, "{}{}=", delimiter, key);
+    write_value(line, value, delimiter);
+}
+
+/// Append `value` to `line`, quoted and escaped if it contains a space, `=`,
+/// `"`, a control character or `delimiter`.
+pub(crate) fn write_value(line: &mut String, value: &str, delimiter: char) {
+    let needs_quote = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c == delimiter || c.is_control());
+
+    if !needs_quote {
+        line.push_str(value);
+        return;
+    }
+
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            // A space is the default delimiter, quoting is enough.
+            c if c == delimiter && c != ' ' => {
+                let _ = write!(line, "\\u{{{:x}}}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
@@ -12,30 +12,96 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::env;
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
 use once_cell::sync::OnceCell;
 use opentelemetry::global;
-use opentelemetry::sdk::propagation::TraceContextPropagator;
-use sentry_tracing::EventFilter;
+use opentelemetry::trace::TraceContextExt;
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+use tracing::warn;
+use tracing::Dispatch;
 use tracing::Level;
 use tracing::Subscriber;
+use tracing_appender::non_blocking::NonBlockingBuilder;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_appender::rolling::RollingFileAppender;
 use tracing_appender::rolling::Rotation;
 use tracing_bunyan_formatter::BunyanFormattingLayer;
+use tracing_bunyan_formatter::JsonStorageLayer;
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::filter::FilterExt;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Identity;
 use tracing_subscriber::Layer;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::Registry;
 
+use crate::active_spans::ActiveSpansLayer;
+use crate::broadcast::BroadcastLayer;
+use crate::buffer_guard::BoundedWriter;
+use crate::buffer_guard::ReleaseOnWrite;
+use crate::buffer_guard::SharedAppender;
+use crate::default_span;
+use crate::default_span::WithDefaultSpan;
+use crate::disk_full::DiskFullWriter;
+use crate::error_buffer::ErrorBufferLayer;
+use crate::error_buffer::ErrorBufferWriter;
+use crate::error_status::ErrorStatusLayer;
+use crate::event_sampling::EventSamplingLayer;
+use crate::exclude::Exclusions;
+use crate::file_appender::FileAppender;
+use crate::file_appender::LocalRollingAppender;
+use crate::file_appender::LocalRotation;
+use crate::global_fields::GlobalFields;
+use crate::level_override::TargetLevelOverrides;
+use crate::limited_fields::LimitedFields;
+use crate::line_ending::LineEndingWriter;
+use crate::logfmt::Logfmt;
+use crate::bunyan_post::BunyanPostProcessor;
+use crate::pipe_writer::PipeTolerant;
+use crate::reload::ReloadHandle;
+use crate::reload::ReloadableFilter;
+use crate::sampled_filter::SampledFilter;
+use crate::sentry_mapper::event_mapper;
+use crate::sidecar::SidecarAppender;
+use crate::sidecar::SidecarTagWriter;
+use crate::silence::SilenceLayer;
+use crate::source_location::CaptureLocation;
+use crate::span_attribute_filter::SpanAttributeAllowlist;
+use crate::span_events::SpanEventsLayer;
+use crate::span_level::SpanLevelFilter;
+use crate::span_path::CaptureSpanPath;
+use crate::span_timing::SpanTimingLayer;
+use crate::summary::shutdown_with_guards;
+use crate::summary::EventCountsLayer;
+use crate::terminal::is_terminal_stderr;
+use crate::themed_text::ThemedText;
+use crate::tracer::install_tracer;
+use crate::tracer::new_propagator;
+use crate::AnsiTheme;
 use crate::Config;
+use crate::CustomTimer;
+use crate::Destination;
+use crate::FileConfig;
+use crate::FlushTimeout;
+use crate::LevelStyle;
+use crate::LogFormat;
+use crate::SourceLocation;
 use crate::singleton_instance::Singleton;
 
+/// Target of the diagnostics emitted by this crate, e.g. a tracer failing to install.
+pub const INTERNAL_TARGET: &str = "common_tracing";
+
 /// Init logging and tracing.
 ///
 /// TODO: we need to unify logging, tracing and metrics.
@@ -48,71 +114,337 @@ use crate::singleton_instance::Singleton;
 ///
 /// To adjust batch sending delay, use `OTEL_BSP_SCHEDULE_DELAY`:
 /// DATABEND_JAEGER_AGENT_ENDPOINT=localhost:6831 RUST_LOG=trace OTEL_BSP_SCHEDULE_DELAY=1 cargo test
+///
+/// The standard `OTEL_EXPORTER_OTLP_ENDPOINT`, `OTEL_SERVICE_NAME` and `OTEL_TRACES_SAMPLER`
+/// are honored too, and take precedence over the Databend specific env vars:
+///   OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4317 RUST_LOG=trace cargo test
 // TODO(xp): use DATABEND_JAEGER_AGENT_ENDPOINT to assign jaeger server address.
 pub fn init_logging(name: &str, cfg: &Config) -> Vec<WorkerGuard> {
+    init_logging_with_outcome(name, cfg).guards
+}
+
+/// What `init_logging_with_outcome` has set up.
+pub struct InitOutcome {
+    /// Guards of the non-blocking writers, must be kept alive while logging.
+    pub guards: Vec<WorkerGuard>,
+    pub layers_enabled: LayersEnabled,
+    /// Changes the file and stderr levels at runtime.
+    pub reload_handle: ReloadHandle,
+    /// Non fatal setup errors, e.g. a tracer failing to install.
+    pub warnings: Vec<String>,
+}
+
+impl InitOutcome {
+    /// Shut down in the order of `shutdown_logging`: flush the traces, then
+    /// the remote sinks, waiting at most `timeout` for each, then the files,
+    /// dropping the guards, and stdout and stderr.
+    pub async fn shutdown(self, timeout: Duration) -> Result<(), FlushTimeout> {
+        shutdown_with_guards(self.guards, timeout).await
+    }
+}
+
+/// Which layers were actually installed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LayersEnabled {
+    pub file: bool,
+    pub stderr: bool,
+    /// The trace exporter layer, exporting to jaeger or OTLP.
+    pub jaeger: bool,
+    pub sentry: bool,
+    pub console: bool,
+    pub otlp_logs: bool,
+    pub webhook: bool,
+}
+
+/// Same as `init_logging`, but also reports which layers were installed.
+pub fn init_logging_with_outcome(name: &str, cfg: &Config) -> InitOutcome {
+    let (subscriber, mut outcome) = build_global_subscriber(name, cfg);
+
+    // Enable log compatible layer to convert log record to tracing span.
+    // `init` fails if a `log` logger is already set, that logger is kept.
+    let log_tracer_error = cfg.capture_log_crate
+        && LogTracer::builder()
+            .with_max_level(cfg.log_crate_level_filter())
+            .init()
+            .is_err();
+    if log_tracer_error {
+        outcome.warnings.insert(
+            0,
+            "a `log` logger is already set, records of the `log` crate are not captured"
+                .to_string(),
+        );
+    }
+
+    // Ignore errors returned by set_global_default.
+    let _ = tracing::subscriber::set_global_default(subscriber);
+
+    // Opened once installed, so that the span is known to the subscriber.
+    if let Some(span) = &cfg.default_span {
+        default_span::open(span);
+    }
+
+    // Internal diagnostics use the `common_tracing` target, e.g. they are
+    // silenced by `RUST_LOG=common_tracing=off`.
+    for warning in &outcome.warnings {
+        warn!(target: INTERNAL_TARGET, "{}", warning);
+    }
+
+    outcome
+}
+
+/// Build the subscriber `init_logging` installs, without installing it, e.g. to
+/// wrap it or to compose it with the subscriber of another framework.
+///
+/// Unlike `init_logging`, it does not capture the records of the `log` crate,
+/// and it does not log `InitOutcome::warnings`: log them once a subscriber is set.
+///
+/// Panics on an invalid filter if `Config::strict_filter` is set.
+pub fn build_global_subscriber(
+    name: &str,
+    cfg: &Config,
+) -> (
+    impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync + 'static,
+    InitOutcome,
+) {
+    let filters = cfg.validate_filters();
+    if cfg.strict_filter {
+        if let Err(e) = &filters {
+            panic!("invalid log filter with strict_filter set, {}", e);
+        }
+    }
+
     let mut guards = vec![];
 
-    let subscriber = Registry::default();
+    // An empty `Vec` of layers hints `OFF` as the max level, which the output
+    // layers do not raise, see `Layer::max_level_hint`: leave the level to them.
+    let mut extra_layers = cfg.extra_layers.take();
+    if extra_layers.is_empty() {
+        extra_layers.push(Identity::new().boxed());
+    }
+    // Events outside any span are recorded in the default span, if configured.
+    let subscriber = WithDefaultSpan::new(
+        Registry::default().with(extra_layers),
+        cfg.default_span.is_some(),
+    );
+
+    // Events rewritten by the overrides are hidden from the output layers.
+    let overrides = TargetLevelOverrides::new(&cfg.target_level_overrides);
+    // Excluded spans and targets are hidden from the output layers as well.
+    let exclusions = Exclusions::new(&cfg.exclude_span_names, &cfg.exclude_targets);
+    let subscriber = subscriber.with(exclusions.marker_layer());
+    // The output layers are boxed in one `Vec` over this subscriber: stacking
+    // them one by one nests their types deep enough to exhaust the compiler.
+    let mut layers = Vec::new();
+    let timer = cfg.resolved_timer();
+
+    // The tracer is installed first, the file layer follows its sampling decisions.
+    let mut tracer_warnings = vec![];
+    let installed_tracer = install_tracer(name, cfg, &mut tracer_warnings);
+    let sampling_tracer = match &installed_tracer {
+        Some((_, Ok(tracer))) => Some(tracer.clone()),
+        _ => None,
+    };
+
+    // Levels of the file and stderr layers, changed by the `ReloadHandle`.
+    let rust_log = env::var(EnvFilter::DEFAULT_ENV);
+    let file_directives = cfg.routed_directives(Destination::File, &cfg.file.level);
+    let file_level = ReloadableFilter::new(&file_directives);
+    let stderr_level = match &rust_log {
+        Ok(directives) => ReloadableFilter::new(directives),
+        Err(_) => {
+            ReloadableFilter::new(&cfg.routed_directives(Destination::Stderr, &cfg.stderr.level))
+        }
+    };
 
     // File Layer
+    let mut main_file_writer = None;
     let file_layer = if cfg.file.on {
-        let rolling_appender = RollingFileAppender::new(Rotation::HOURLY, &cfg.file.dir, name);
+        let dir = cfg.file.resolved_dir();
+        // The lines of a JSON sidecar are tagged for the appender writing both.
+        let json_sidecar = cfg.file.json_sidecar && cfg.file.format != LogFormat::Json;
+        let appender = new_main_appender(&dir, name, &cfg.file)
+            .expect("initializing rolling file appender failed");
         let (rolling_writer, rolling_writer_guard) =
-            tracing_appender::non_blocking(rolling_appender);
+            new_appender_writer(appender, &dir, name, &cfg.file, "log-file-writer");
+        // Kept by the `ReloadHandle` to move the files.
+        main_file_writer = Some(rolling_writer.clone());
+        // The verbose lines of the buffering spans are held back until an error.
+        let error_buffer = (!cfg.error_buffer.span_names.is_empty())
+            .then(|| ErrorBufferLayer::new(&cfg.error_buffer, rolling_writer.clone()));
+        let rolling_writer = ErrorBufferWriter::new(rolling_writer);
+        // Stacked after the text or logfmt layer, writing the line of the event first.
+        let sidecar = json_sidecar.then(|| {
+            bunyan_file_layer(name, cfg, SidecarTagWriter::sidecar(rolling_writer.clone()))
+        });
 
-        let file_logging_layer = BunyanFormattingLayer::new(name.to_string(), rolling_writer);
+        // Events routed to the sinks are not written to the main file.
+        let sinks = cfg.file.sinks.clone();
+        let filter = file_level
+            .clone()
+            .or(SpanLevelFilter::new(&cfg.span_level_field))
+            .and(overrides.suppress_filter())
+            .and(exclusions.filter())
+            .and(SampledFilter::new(
+                sampling_tracer,
+                cfg.file.unsampled_level_filter(),
+            ))
+            .and(filter_fn(move |meta| {
+                !sinks.iter().any(|sink| sink.routes(meta.target()))
+            }));
 
-        let filter = EnvFilter::new(&cfg.file.level);
-        let file = file_logging_layer.with_filter(filter);
+        let file = match cfg.file.format {
+            LogFormat::Json => bunyan_file_layer(name, cfg, rolling_writer)
+                .with_filter(filter)
+                .boxed(),
+            // Only `ThemedText` renders the level style.
+            LogFormat::Text if cfg.level_style != LevelStyle::Full => fmt::layer()
+                .with_ansi(false)
+                .fmt_fields(text_fields(cfg))
+                .event_format(GlobalFields::new(
+                    ThemedText::new(timer.clone(), AnsiTheme::default())
+                        .with_level_style(cfg.level_style),
+                    cfg,
+                    false,
+                ))
+                .with_writer(LineEndingWriter::new(
+                    SidecarTagWriter::log(rolling_writer, json_sidecar),
+                    cfg.line_ending,
+                ))
+                .and_then(sidecar)
+                .with_filter(filter)
+                .boxed(),
+            LogFormat::Text => fmt::layer()
+                .with_ansi(false)
+                .fmt_fields(text_fields(cfg))
+                .with_timer(timer.clone())
+                .map_event_format(|f| GlobalFields::new(f, cfg, false))
+                .with_writer(LineEndingWriter::new(
+                    SidecarTagWriter::log(rolling_writer, json_sidecar),
+                    cfg.line_ending,
+                ))
+                .and_then(sidecar)
+                .with_filter(filter)
+                .boxed(),
+            LogFormat::Logfmt => fmt::layer()
+                .event_format(
+                    GlobalFields::new(
+                        Logfmt::new()
+                            .with_timer(timer.clone())
+                            .with_level_style(cfg.level_style)
+                            .with_max_fields(cfg.max_fields_per_event)
+                            .with_delimiter(cfg.logfmt_delimiter),
+                        cfg,
+                        false,
+                    )
+                    .with_logfmt_delimiter(cfg.logfmt_delimiter),
+                )
+                .with_writer(LineEndingWriter::new(
+                    SidecarTagWriter::log(rolling_writer, json_sidecar),
+                    cfg.line_ending,
+                ))
+                .and_then(sidecar)
+                .with_filter(filter)
+                .boxed(),
+        };
 
         guards.push(rolling_writer_guard);
 
-        Some(file)
+        Some(Layer::and_then(error_buffer, file))
     } else {
         None
     };
-    let subscriber = subscriber.with(file_layer);
+    let file_enabled = file_layer.is_some();
+    layers.push(file_layer.boxed());
+
+    // File sink layers, one per sink, each only receiving its routed targets.
+    if cfg.file.on {
+        for sink in &cfg.file.sinks {
+            let (rolling_writer, rolling_writer_guard) = new_file_writer(
+                &cfg.file.resolved_dir(),
+                &sink.name,
+                &cfg.file,
+                "log-sink-writer",
+            );
+            guards.push(rolling_writer_guard);
+
+            let routed = sink.clone();
+            let filter = EnvFilter::new(&sink.level)
+                .and(filter_fn(move |meta| routed.routes(meta.target())))
+                .and(exclusions.filter());
+            let layer = fmt_layer(cfg, sink.format, timer.clone(), rolling_writer, false);
+            layers.push(layer.with_filter(filter).boxed());
+        }
+    }
 
     // Stderr (Console) Layer
-    let rust_log = env::var(EnvFilter::DEFAULT_ENV);
     let stderr_layer = if cfg.stderr.on || rust_log.is_ok() {
         // Use env RUST_LOG to initialize log if present.
         // Otherwise, use the specified level.
-        let directives = rust_log.unwrap_or_else(|_| cfg.stderr.level.to_string());
-        let env_filter = EnvFilter::new(directives);
+        let env_filter = stderr_level
+            .clone()
+            .or(SpanLevelFilter::new(&cfg.span_level_field))
+            .and(overrides.suppress_filter())
+            .and(exclusions.filter());
 
-        let stderr = fmt::layer().with_writer(io::stderr).with_filter(env_filter);
+        // No ANSI escapes if stderr is redirected, e.g. to a file by systemd.
+        let stderr = fmt_layer(
+            cfg,
+            cfg.stderr.format,
+            timer.clone(),
+            PipeTolerant::new(io::stderr),
+            is_terminal_stderr(),
+        )
+        .with_filter(env_filter);
 
         Some(stderr)
     } else {
         None
     };
-    let subscriber = subscriber.with(stderr_layer);
+    let stderr_enabled = stderr_layer.is_some();
+    layers.push(stderr_layer.boxed());
 
-    // Jaeger layer.
+    // Jaeger(or OTLP) layer.
     // TODO: we should support config this in the future.
     let mut jaeger_layer = None;
-    let jaeger_agent_endpoint =
-        env::var("DATABEND_JAEGER_AGENT_ENDPOINT").unwrap_or_else(|_| "".to_string());
-    if !jaeger_agent_endpoint.is_empty() {
-        global::set_text_map_propagator(TraceContextPropagator::new());
-
-        let tracer = opentelemetry_jaeger::new_agent_pipeline()
-            .with_service_name(name)
-            .with_endpoint(jaeger_agent_endpoint)
-            .with_auto_split_batch(true)
-            .install_batch(opentelemetry::runtime::Tokio)
-            .expect("install");
-
-        // Load filter from `RUST_LOG`. Default to `ERROR`.
-        let env_filter = EnvFilter::from_default_env();
-        jaeger_layer = Some(
-            tracing_opentelemetry::layer()
-                .with_tracer(tracer)
-                .with_filter(env_filter),
-        );
+    // Tracer setup errors are not fatal, they are reported once the
+    // subscriber is installed so that they reach the other layers.
+    let mut jaeger_error = None;
+    if let Some((endpoint, installed)) = installed_tracer {
+        // Set for every exporter, even if it failed to install: the context is
+        // still propagated to the downstream services.
+        global::set_text_map_propagator(new_propagator(cfg.trace.propagator));
+
+        match installed {
+            Ok(tracer) => {
+                // Load filter from `RUST_LOG`. Default to `ERROR`.
+                let env_filter = EnvFilter::from_default_env()
+                    .and(cfg.trace.export_level_filter())
+                    .and(overrides.suppress_filter())
+                    .and(exclusions.filter());
+                let allowlist = if cfg.trace.tag_allowlist.is_empty() {
+                    None
+                } else {
+                    Some(SpanAttributeAllowlist::new(&cfg.trace.tag_allowlist))
+                };
+                let error_status = (cfg.trace.error_events_set_status
+                    || cfg.trace.event_level_attributes)
+                    .then(|| {
+                        ErrorStatusLayer::new()
+                            .with_status(cfg.trace.error_events_set_status)
+                            .with_level_attributes(cfg.trace.event_level_attributes)
+                    });
+                // The error status layer completes what the OpenTelemetry layer records.
+                let otel = tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .and_then(error_status);
+                // `allowlist` is an `Option`, whose own `and_then` is not the layer one.
+                jaeger_layer = Some(Layer::and_then(allowlist, otel).with_filter(env_filter));
+            }
+            Err(e) => jaeger_error = Some((endpoint, e)),
+        }
     }
-    let subscriber = subscriber.with(jaeger_layer);
+    let jaeger_enabled = jaeger_layer.is_some();
+    layers.push(jaeger_layer.boxed());
 
     // Sentry Layer.
     // TODO: we should support config this in the future.
@@ -121,55 +453,510 @@ pub fn init_logging(name: &str, cfg: &Config) -> Vec<WorkerGuard> {
     if !bend_sentry_env.is_empty() {
         sentry_layer = Some(
             sentry_tracing::layer()
-                .event_filter(|metadata| match metadata.level() {
-                    &Level::ERROR | &Level::WARN => EventFilter::Event,
-                    &Level::INFO | &Level::DEBUG | &Level::TRACE => EventFilter::Breadcrumb,
-                })
+                .event_mapper(event_mapper(&cfg.sentry))
                 .span_filter(|metadata| {
                     matches!(
                         metadata.level(),
                         &Level::ERROR | &Level::WARN | &Level::INFO | &Level::DEBUG
                     )
-                }),
+                })
+                .with_filter(
+                    cfg.trace
+                        .export_level_filter()
+                        .and(
+                            cfg.routed_level(Destination::Sentry)
+                                .unwrap_or(LevelFilter::TRACE),
+                        )
+                        .and(overrides.suppress_filter())
+                        .and(exclusions.filter()),
+                ),
         );
     }
-    let subscriber = subscriber.with(sentry_layer);
+    let sentry_enabled = sentry_layer.is_some();
+    layers.push(sentry_layer.boxed());
+
+    // Target level overrides layer, re-emits the hidden events at the downgraded level.
+    let overrides_layer = if overrides.is_empty() {
+        None
+    } else {
+        Some(overrides.clone().with_filter(overrides.layer_filter()))
+    };
+    layers.push(overrides_layer.boxed());
+
+    // OTLP logs layer.
+    #[cfg(not(feature = "otlp-logs"))]
+    let otlp_logs_enabled = false;
+    #[cfg(feature = "otlp-logs")]
+    let mut otlp_logs_error = None;
+    #[cfg(feature = "otlp-logs")]
+    let otlp_logs_enabled = {
+        let mut otlp_logs_layer = None;
+        if cfg.otlp_logs.on {
+            let layer = crate::otlp_logs::OtlpLogsLayer::new(
+                name,
+                &cfg.otlp_logs.endpoint,
+                &cfg.global_fields,
+            );
+            match layer {
+                Ok(layer) => {
+                    let level = cfg.routed_directives(Destination::OtlpLogs, &cfg.otlp_logs.level);
+                    let filter = EnvFilter::new(level).and(exclusions.filter());
+                    otlp_logs_layer = Some(layer.with_filter(filter));
+                }
+                Err(e) => otlp_logs_error = Some(e),
+            }
+        }
+        let enabled = otlp_logs_layer.is_some();
+        layers.push(otlp_logs_layer.boxed());
+        enabled
+    };
+
+    // Webhook layer, for alerting.
+    #[cfg(not(feature = "http-sink"))]
+    let webhook_enabled = false;
+    #[cfg(feature = "http-sink")]
+    let mut webhook_error = None;
+    #[cfg(feature = "http-sink")]
+    let webhook_enabled = {
+        let mut webhook_layer = None;
+        if cfg.webhook.on {
+            match crate::webhook::WebhookLayer::new(name, &cfg.webhook) {
+                Ok(layer) => {
+                    let level = cfg.routed_directives(Destination::Webhook, &cfg.webhook.level);
+                    let filter = EnvFilter::new(level).and(exclusions.filter());
+                    webhook_layer = Some(layer.with_filter(filter));
+                }
+                Err(e) => webhook_error = Some(e),
+            }
+        }
+        let enabled = webhook_layer.is_some();
+        layers.push(webhook_layer.boxed());
+        enabled
+    };
+
+    // Log events counter, reported through the `metrics` crate.
+    // Filtered by the file level, otherwise it would enable every single event.
+    #[cfg(feature = "metrics")]
+    layers.push(
+        crate::log_metrics::LogMetricsLayer
+            .with_filter(EnvFilter::new(&file_directives))
+            .boxed(),
+    );
+
+    // Event counts of the shutdown summary, filtered by the file level as well.
+    layers.push(
+        EventCountsLayer
+            .with_filter(EnvFilter::new(&file_directives))
+            .boxed(),
+    );
+
+    // Windows Event Log layer.
+    #[cfg(all(windows, feature = "winlog"))]
+    let mut winlog_error = None;
+    #[cfg(all(windows, feature = "winlog"))]
+    {
+        let winlog_layer = match crate::winlog::EventLogLayer::new(name) {
+            Ok(layer) => Some(layer.with_filter(LevelFilter::WARN)),
+            Err(e) => {
+                winlog_error = Some(e);
+                None
+            }
+        };
+        layers.push(winlog_layer.boxed());
+    }
+
+    // Broadcast layer, forwards events to the runtime consumers.
+    let broadcast_layer = if cfg.broadcast.on {
+        let level = cfg.routed_directives(Destination::Broadcast, &cfg.broadcast.level);
+        let filter = EnvFilter::new(level).and(exclusions.filter());
+        Some(BroadcastLayer.with_filter(filter))
+    } else {
+        None
+    };
+    layers.push(broadcast_layer.boxed());
+
+    // Active spans layer, for dumping the open spans. It needs no event.
+    let active_spans_layer = if cfg.track_active_spans {
+        Some(ActiveSpansLayer.with_filter(filter_fn(|meta| meta.is_span())))
+    } else {
+        None
+    };
+    layers.push(active_spans_layer.boxed());
+
+    // Span timing layer, it only emits events on span close.
+    let span_timing_layer = if cfg.span_timing {
+        let filter = filter_fn(|meta| meta.is_span()).and(exclusions.filter());
+        Some(SpanTimingLayer.with_filter(filter))
+    } else {
+        None
+    };
+    layers.push(span_timing_layer.boxed());
+
+    // Silence layer, vetoes the events silenced on the emitting thread.
+    layers.push(SilenceLayer.boxed());
+
+    // Event sampling layer, placed last since it only vetoes events.
+    let sampling_layer = if cfg.event_sampling.field.is_empty() {
+        None
+    } else {
+        Some(EventSamplingLayer::new(&cfg.event_sampling))
+    };
+    layers.push(sampling_layer.boxed());
 
     // For tokio-console
+    let console_enabled = cfg!(feature = "console");
     #[cfg(feature = "console")]
-        let subscriber = subscriber.with(console_subscriber::spawn());
+    layers.push(console_subscriber::spawn().boxed());
+    let subscriber = subscriber.with(layers);
 
-    // Enable log compatible layer to convert log record to tracing span.
-    // We will ignore any errors that returned by this fucntions.
-    let _ = LogTracer::init();
+    // Collected here, logged by the caller once a subscriber is installed.
+    let mut warnings = tracer_warnings;
+    if let Err(e) = filters {
+        warnings.push(e.to_string());
+    }
 
-    // Ignore errors returned by set_global_default.
-    let _ = tracing::subscriber::set_global_default(subscriber);
+    // Span records are written with the Bunyan ones only.
+    if cfg.file.on
+        && cfg.file.span_events
+        && cfg.file.format != LogFormat::Json
+        && !cfg.file.json_sidecar
+    {
+        warnings.push(
+            "file.span_events requires the json file format or file.json_sidecar, no span records are written"
+                .to_string(),
+        );
+    }
+
+    if let Some((endpoint, e)) = jaeger_error {
+        warnings.push(format!(
+            "failed to install tracer for endpoint {}, continue without tracing: {}",
+            endpoint, e
+        ));
+    }
+
+    #[cfg(feature = "otlp-logs")]
+    if let Some(e) = otlp_logs_error {
+        warnings.push(format!(
+            "failed to create otlp logs exporter for {}, continue without otlp logs: {}",
+            cfg.otlp_logs.endpoint, e
+        ));
+    }
+
+    #[cfg(feature = "http-sink")]
+    if let Some(e) = webhook_error {
+        warnings.push(format!(
+            "failed to create webhook client for {}, continue without webhook: {}",
+            cfg.webhook.url, e
+        ));
+    }
+
+    #[cfg(all(windows, feature = "winlog"))]
+    if let Some(e) = winlog_error {
+        warnings.push(format!(
+            "failed to register windows event source {}: {}",
+            name, e
+        ));
+    }
+
+    let outcome = InitOutcome {
+        guards,
+        reload_handle: ReloadHandle::new(file_level, stderr_level, cfg.clone())
+            .with_file_writer(name, main_file_writer),
+        layers_enabled: LayersEnabled {
+            file: file_enabled,
+            stderr: stderr_enabled,
+            jaeger: jaeger_enabled,
+            sentry: sentry_enabled,
+            console: console_enabled,
+            otlp_logs: otlp_logs_enabled,
+            webhook: webhook_enabled,
+        },
+        warnings,
+    };
+    (subscriber, outcome)
+}
+
+/// The Bunyan layer of the `json` records of the main file, as configured by `cfg`.
+fn bunyan_file_layer<S, W>(name: &str, cfg: &Config, writer: W) -> impl Layer<S> + Send + Sync
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Clone + Send + Sync + 'static,
+{
+    // Bunyan always emits `hostname` and `pid` as core fields, only the
+    // application specific ones have to be added here.
+    let mut default_fields = HashMap::new();
+    if !cfg.app_version.is_empty() {
+        default_fields.insert(
+            "app_version".to_string(),
+            serde_json::Value::from(cfg.app_version.clone()),
+        );
+    }
+    for (k, v) in &cfg.global_fields {
+        default_fields.insert(k.clone(), serde_json::Value::from(v.clone()));
+    }
+    // Stacked before Bunyan: the open record precedes the `[SPAN] START` one.
+    let span_events = cfg
+        .file
+        .span_events
+        .then(|| SpanEventsLayer::new(writer.clone()));
+    let file_logging_layer = BunyanFormattingLayer::with_default_fields(
+        name.to_string(),
+        BunyanPostProcessor::new(writer, &cfg.file.message_field)
+            .with_max_fields(cfg.max_fields_per_event)
+            .with_seq(cfg.seq_field)
+            .with_uptime(cfg.uptime_field)
+            .with_name_from(&cfg.file.name_field)
+            .with_source_location(cfg.json_source_location)
+            .with_span_path(cfg.span_path_field),
+        default_fields,
+    );
+    let capture_location =
+        (cfg.json_source_location == SourceLocation::Include).then_some(CaptureLocation);
+    let capture_span_path = cfg.span_path_field.then_some(CaptureSpanPath);
+
+    // `JsonStorageLayer` keeps the span fields, so that Bunyan emits them with the events.
+    JsonStorageLayer
+        .and_then(capture_location)
+        .and_then(capture_span_path)
+        .and_then(span_events)
+        .and_then(file_logging_layer)
+}
+
+/// Create a fmt layer writing to `writer` in `format`,
+/// with the global fields of `cfg`, themed by `cfg.stderr.theme` if `ansi`.
+/// Lines but JSON ones end with `cfg.line_ending`.
+pub(crate) fn fmt_layer<S, W>(
+    cfg: &Config,
+    format: LogFormat,
+    timer: CustomTimer,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        // Only `ThemedText` renders the level style.
+        LogFormat::Text
+            if (ansi && cfg.stderr.theme.is_some()) || cfg.level_style != LevelStyle::Full =>
+        {
+            fmt::layer()
+                .with_ansi(ansi)
+                .fmt_fields(text_fields(cfg))
+                .event_format(GlobalFields::new(
+                    ThemedText::new(timer, cfg.stderr.theme.unwrap_or_default())
+                        .with_level_style(cfg.level_style),
+                    cfg,
+                    false,
+                ))
+                .with_writer(LineEndingWriter::new(writer, cfg.line_ending))
+                .boxed()
+        }
+        LogFormat::Text => fmt::layer()
+            .with_ansi(ansi)
+            .fmt_fields(text_fields(cfg))
+            .with_timer(timer)
+            .map_event_format(|f| GlobalFields::new(f, cfg, false))
+            .with_writer(LineEndingWriter::new(writer, cfg.line_ending))
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_ansi(ansi)
+            .with_timer(timer)
+            .map_event_format(|f| GlobalFields::new(f, cfg, true))
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Logfmt => fmt::layer()
+            .event_format(
+                GlobalFields::new(
+                    Logfmt::new()
+                        .with_timer(timer)
+                        .with_level_style(cfg.level_style)
+                        .with_max_fields(cfg.max_fields_per_event)
+                        .with_delimiter(cfg.logfmt_delimiter),
+                    cfg,
+                    false,
+                )
+                .with_logfmt_delimiter(cfg.logfmt_delimiter),
+            )
+            .with_writer(LineEndingWriter::new(writer, cfg.line_ending))
+            .boxed(),
+    }
+}
+
+/// Create a file appender in `dir`, rotated and cleaned up as configured by `cfg`.
+///
+/// Compression of rotated files is not supported by `tracing-appender`,
+/// use an external tool such as logrotate if it is required.
+pub(crate) fn new_rolling_appender(dir: &str, prefix: &str, cfg: &FileConfig) -> FileAppender {
+    try_new_rolling_appender(dir, prefix, cfg).expect("initializing rolling file appender failed")
+}
+
+/// Same as `new_rolling_appender`, but returns the error instead of panicking.
+pub(crate) fn try_new_rolling_appender(
+    dir: &str,
+    prefix: &str,
+    cfg: &FileConfig,
+) -> io::Result<FileAppender> {
+    let rotation = cfg.rotation.to_lowercase();
+
+    // `RollingFileAppender` can not cap the total bytes, the files are named the same.
+    if cfg.local_time_file_names || cfg.max_total_bytes.is_some() {
+        return Ok(FileAppender::Local(new_local_appender(dir, prefix, cfg)?));
+    }
+
+    let rotation = match rotation.as_str() {
+        "minutely" => Rotation::MINUTELY,
+        "daily" => Rotation::DAILY,
+        "never" => Rotation::NEVER,
+        _ => Rotation::HOURLY,
+    };
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(prefix);
+    if cfg.max_files > 0 {
+        builder = builder.max_log_files(cfg.max_files);
+    }
+
+    let appender = builder.build(dir).map_err(io::Error::other)?;
+    Ok(FileAppender::Utc(appender))
+}
+
+/// Create a `LocalRollingAppender` in `dir`, rotated and cleaned up as configured by `cfg`.
+fn new_local_appender(
+    dir: &str,
+    prefix: &str,
+    cfg: &FileConfig,
+) -> io::Result<LocalRollingAppender> {
+    let rotation = match cfg.rotation.to_lowercase().as_str() {
+        "minutely" => LocalRotation::Minutely,
+        "daily" => LocalRotation::Daily,
+        "never" => LocalRotation::Never,
+        _ => LocalRotation::Hourly,
+    };
+    let appender = if cfg.local_time_file_names {
+        LocalRollingAppender::new(dir, prefix, rotation, cfg.max_files)
+    } else {
+        LocalRollingAppender::new_utc(dir, prefix, rotation, cfg.max_files)
+    };
+    Ok(appender?.with_max_total_bytes(cfg.max_total_bytes))
+}
+
+/// Create the appender of the files of `prefix` and of its JSON sidecar
+/// `<prefix>.json` in `dir`, rotated together.
+fn new_sidecar_appender(dir: &str, prefix: &str, cfg: &FileConfig) -> io::Result<FileAppender> {
+    let log = new_local_appender(dir, prefix, cfg)?;
+    let sidecar = new_local_appender(dir, &format!("{}.json", prefix), cfg)?;
+    Ok(FileAppender::Sidecar(SidecarAppender::new(log, sidecar)))
+}
+
+/// Create the appender of the main log files of `name` in `dir`, with their
+/// JSON sidecar if configured.
+pub(crate) fn new_main_appender(
+    dir: &str,
+    name: &str,
+    cfg: &FileConfig,
+) -> io::Result<FileAppender> {
+    if cfg.json_sidecar && cfg.format != LogFormat::Json {
+        new_sidecar_appender(dir, name, cfg)
+    } else {
+        try_new_rolling_appender(dir, name, cfg)
+    }
+}
 
-    guards
+/// The fields formatter of the text lines, as configured by `cfg`.
+fn text_fields(cfg: &Config) -> LimitedFields {
+    LimitedFields::new(cfg.max_fields_per_event).with_formatter(cfg.field_formatter.clone())
 }
 
+/// Create a non-blocking writer of the rolling files of `prefix` in `dir`,
+/// buffered as configured by `cfg`.
+///
+/// The background worker thread is named `thread_name`, to be found in thread
+/// dumps and `top -H`. Linux shows the first 15 bytes of a thread name only.
+fn new_file_writer(
+    dir: &str,
+    prefix: &str,
+    cfg: &FileConfig,
+    thread_name: &str,
+) -> (BoundedWriter, WorkerGuard) {
+    let appender = new_rolling_appender(dir, prefix, cfg);
+    new_appender_writer(appender, dir, prefix, cfg, thread_name)
+}
+
+/// Create a non-blocking writer of `appender`, writing the files of `prefix` in `dir`.
+fn new_appender_writer(
+    appender: FileAppender,
+    dir: &str,
+    prefix: &str,
+    cfg: &FileConfig,
+    thread_name: &str,
+) -> (BoundedWriter, WorkerGuard) {
+    let appender = DiskFullWriter::new(appender, dir, prefix, cfg.disk_full_policy);
+    let appender = SharedAppender::new(appender);
+    let (writer, guard) = NonBlockingBuilder::default()
+        .buffered_lines_limit(cfg.buffered_lines_limit)
+        .lossy(cfg.lossy)
+        .thread_name(thread_name)
+        .finish(ReleaseOnWrite(appender.clone()));
+
+    let mut writer = BoundedWriter::new(writer, appender, cfg.max_buffered_bytes);
+    if cfg.sync_error_flush {
+        writer = writer.with_sync_errors();
+    }
+    (writer, guard)
+}
+
+/// Init the logger writes query details into files in `dir`, rotated hourly
+/// and without timestamps, as with the default `FileConfig`.
+///
+/// To write the events of a target to their own file from the global
+/// subscriber instead, see `FileConfig::sinks`.
 pub fn init_query_logger(
     log_name: &str,
     dir: &str,
+) -> (Vec<WorkerGuard>, Arc<dyn Subscriber + Send + Sync>) {
+    init_query_logger_with_config(log_name, dir, &FileConfig::default())
+}
+
+/// Like `init_query_logger`, with the files rotated and cleaned up with the
+/// same options as the main file logging, `rotation` and `max_files` in `cfg`.
+/// Lines have no timestamp unless `query_log_time` is set.
+pub fn init_query_logger_with_config(
+    log_name: &str,
+    dir: &str,
+    cfg: &FileConfig,
 ) -> (Vec<WorkerGuard>, Arc<dyn Subscriber + Send + Sync>) {
     let mut guards = vec![];
 
-    let rolling_appender = RollingFileAppender::new(Rotation::HOURLY, dir, log_name);
-    let (rolling_writer, rolling_writer_guard) = tracing_appender::non_blocking(rolling_appender);
+    let (rolling_writer, rolling_writer_guard) =
+        new_file_writer(dir, log_name, cfg, "log-query-writer");
     let format = tracing_subscriber::fmt::format()
-        .without_time()
         .with_target(false)
         .with_level(false)
         .compact();
     guards.push(rolling_writer_guard);
 
-    let subscriber = tracing_subscriber::fmt()
-        .with_writer(rolling_writer)
-        .event_format(format)
-        .finish();
+    // The default timer writes RFC3339 UTC timestamps, e.g. `2022-10-01T08:00:00.000000Z`.
+    let subscriber: Arc<dyn Subscriber + Send + Sync> = if cfg.query_log_time {
+        Arc::new(
+            tracing_subscriber::fmt()
+                .with_writer(rolling_writer)
+                .event_format(format)
+                .finish(),
+        )
+    } else {
+        Arc::new(
+            tracing_subscriber::fmt()
+                .with_writer(rolling_writer)
+                .event_format(format.without_time())
+                .finish(),
+        )
+    };
 
-    (guards, Arc::new(subscriber))
+    (guards, subscriber)
 }
 
 pub struct QueryLogger {
@@ -192,11 +979,15 @@ impl QueryLogger {
     ) -> anyhow::Result<()> {
         let app_name = format!("databend-query-{}", app_name_shuffle);
         let mut _log_guards = init_logging(app_name.as_str(), config);
-        let query_detail_dir = format!("{}/query-detail", config.file.dir);
+        let query_detail_dir = format!("{}/query-detail", config.file.resolved_dir());
 
         v.init(match config.file.on {
             true => {
-                let (_guards, subscriber) = init_query_logger(&app_name_shuffle, &query_detail_dir);
+                let (_guards, subscriber) = init_query_logger_with_config(
+                    &app_name_shuffle,
+                    &query_detail_dir,
+                    &config.file,
+                );
                 _log_guards.extend(_guards);
 
                 Arc::new(QueryLogger {
@@ -224,4 +1015,113 @@ impl QueryLogger {
     pub fn get_subscriber(&self) -> Option<Arc<dyn Subscriber + Send + Sync>> {
         self.subscriber.clone()
     }
+
+    /// Write a query to the query log, with the same fields for every query:
+    ///
+    /// `query query_id="..." sql="..." duration_ms=12 rows=3 status="ok" trace_id=.. span_id=..`
+    ///
+    /// `trace_id` and `span_id` are the OpenTelemetry ids of the current span,
+    /// omitted if there is none. Does nothing if the query logger is disabled.
+    pub fn record(&self, query_id: &str, sql: &str, duration: Duration, rows: u64, status: &str) {
+        if let Some(subscriber) = &self.subscriber {
+            // The query log subscriber has no span of the request, take the ids before switching.
+            let cx = tracing::Span::current().context();
+            let span_context = cx.span().span_context().clone();
+            let (trace_id, span_id) = if span_context.is_valid() {
+                (
+                    Some(span_context.trace_id().to_string()),
+                    Some(span_context.span_id().to_string()),
+                )
+            } else {
+                (None, None)
+            };
+
+            let dispatch = Dispatch::new(subscriber.clone());
+            tracing::dispatcher::with_default(&dispatch, || {
+                info!(
+                    query_id,
+                    sql,
+                    duration_ms = duration.as_millis() as u64,
+                    rows,
+                    status,
+                    trace_id = trace_id.as_deref(),
+                    span_id = span_id.as_deref(),
+                    "query"
+                );
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    use tracing::info_span;
+
+    use super::*;
+
+    /// Captures the lines written by a layer.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Capture {
+        fn records(&self) -> Vec<serde_json::Value> {
+            let buf = self.0.lock().unwrap();
+            String::from_utf8_lossy(&buf)
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        }
+    }
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Capture;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_fields_keep_their_types() {
+        let capture = Capture::default();
+        let subscriber = Registry::default().with(bunyan_file_layer(
+            "test",
+            &Config::default(),
+            capture.clone(),
+        ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = info_span!("request", rows = 7_u64, cached = false).entered();
+            info!(count = 5, ratio = 0.5, ok = true, id = %5, "done");
+        });
+
+        let records = capture.records();
+        let event = records.iter().find(|r| r["msg"] == "[REQUEST - EVENT] done").unwrap();
+        assert_eq!(event["count"], serde_json::json!(5));
+        assert_eq!(event["ratio"], serde_json::json!(0.5));
+        assert_eq!(event["ok"], serde_json::json!(true));
+        // Formatted with `%`, thus a string.
+        assert_eq!(event["id"], serde_json::json!("5"));
+        // The span fields are merged into the event.
+        assert_eq!(event["rows"], serde_json::json!(7));
+        assert_eq!(event["cached"], serde_json::json!(false));
+
+        let line = String::from_utf8_lossy(&capture.0.lock().unwrap()).to_string();
+        assert!(line.contains(r#""count":5"#), "{}", line);
+        assert!(!line.contains(r#""count":"5""#), "{}", line);
+    }
 }
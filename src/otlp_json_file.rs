@@ -0,0 +1,157 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use opentelemetry::sdk::export::trace::ExportResult;
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::export::trace::SpanExporter;
+use opentelemetry::trace::SpanId;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::trace::Status;
+use opentelemetry::trace::TraceError;
+use opentelemetry::Key;
+use opentelemetry::Value;
+use serde_json::json;
+
+use crate::file_appender::FileAppender;
+
+/// Exports spans to files as OTLP JSON, in the layout of the OpenTelemetry
+/// collector file exporter: every line is an `ExportTraceServiceRequest` of a batch.
+#[derive(Debug)]
+pub(crate) struct OtlpJsonFileExporter {
+    appender: FileAppender,
+    resource: serde_json::Value,
+}
+
+impl OtlpJsonFileExporter {
+    pub(crate) fn new(
+        appender: FileAppender,
+        service_name: &str,
+        resource_attrs: &[(String, String)],
+    ) -> Self {
+        let mut attributes = vec![string_attribute("service.name", service_name)];
+        for (k, v) in resource_attrs {
+            attributes.push(string_attribute(k, v));
+        }
+
+        Self {
+            appender,
+            resource: json!({ "attributes": attributes }),
+        }
+    }
+}
+
+impl SpanExporter for OtlpJsonFileExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let spans = batch.iter().map(span_json).collect::<Vec<_>>();
+        let request = json!({
+            "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{
+                    "scope": { "name": "common_tracing" },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        let mut line = request.to_string();
+        line.push('\n');
+        let result = self
+            .appender
+            .write_all(line.as_bytes())
+            .map_err(|e| TraceError::Other(Box::new(e)));
+        Box::pin(std::future::ready(result))
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.appender.flush();
+    }
+}
+
+fn span_json(span: &SpanData) -> serde_json::Value {
+    let parent_span_id = if span.parent_span_id == SpanId::INVALID {
+        "".to_string()
+    } else {
+        span.parent_span_id.to_string()
+    };
+
+    // Codes of the OTLP `Status.StatusCode` and `Span.SpanKind` enums.
+    let status = match &span.status {
+        Status::Unset => json!({ "code": 0 }),
+        Status::Ok => json!({ "code": 1 }),
+        Status::Error { description } => json!({ "code": 2, "message": description }),
+    };
+    let kind = match span.span_kind {
+        SpanKind::Internal => 1,
+        SpanKind::Server => 2,
+        SpanKind::Client => 3,
+        SpanKind::Producer => 4,
+        SpanKind::Consumer => 5,
+    };
+
+    let events = span
+        .events
+        .iter()
+        .map(|event| {
+            json!({
+                "timeUnixNano": unix_nanos(event.timestamp),
+                "name": event.name,
+                "attributes": event.attributes.iter().map(|kv| attribute(&kv.key, &kv.value)).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "traceId": span.span_context.trace_id().to_string(),
+        "spanId": span.span_context.span_id().to_string(),
+        "parentSpanId": parent_span_id,
+        "name": span.name,
+        "kind": kind,
+        "startTimeUnixNano": unix_nanos(span.start_time),
+        "endTimeUnixNano": unix_nanos(span.end_time),
+        "attributes": span.attributes.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+        "events": events,
+        "status": status,
+    })
+}
+
+/// Nanoseconds since the epoch, as a string as required for 64 bit integers in OTLP JSON.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn attribute(key: &Key, value: &Value) -> serde_json::Value {
+    let value = match value {
+        Value::Bool(v) => json!({ "boolValue": v }),
+        Value::I64(v) => json!({ "intValue": v.to_string() }),
+        Value::F64(v) => json!({ "doubleValue": v }),
+        v => json!({ "stringValue": v.as_str() }),
+    };
+    json!({ "key": key.as_str(), "value": value })
+}
+
+fn string_attribute(key: &str, value: &str) -> serde_json::Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
@@ -0,0 +1,264 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::any_value;
+use opentelemetry_proto::tonic::common::v1::AnyValue;
+use opentelemetry_proto::tonic::common::v1::KeyValue;
+use opentelemetry_proto::tonic::logs::v1::InstrumentationLibraryLogs;
+use opentelemetry_proto::tonic::logs::v1::LogRecord;
+use opentelemetry_proto::tonic::logs::v1::ResourceLogs;
+use opentelemetry_proto::tonic::logs::v1::SeverityNumber;
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use tokio::sync::mpsc;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::remote_sinks;
+use crate::remote_sinks::FailureReporter;
+use crate::remote_sinks::RemoteSinkError;
+use crate::remote_sinks::SinkMessage;
+use crate::tracing_to_jaeger::otel_ids;
+
+/// Max number of records sent in one export request.
+const MAX_EXPORT_BATCH_SIZE: usize = 512;
+
+/// Max number of records waiting for export, new records are dropped when full.
+const MAX_QUEUE_SIZE: usize = 2048;
+
+/// How long a partial batch waits before it is exported.
+const SCHEDULED_DELAY: Duration = Duration::from_secs(1);
+
+/// A layer exports tracing events as OTLP log records to a collector.
+///
+/// Events are queued and exported in batches by a background tokio task,
+/// thus this must be created inside a tokio runtime, `new` fails otherwise. Records are dropped if
+/// the queue is full, logging never blocks on the collector. The queued
+/// records are exported by `shutdown_logging`.
+pub struct OtlpLogsLayer {
+    tx: mpsc::Sender<SinkMessage<LogRecord>>,
+}
+
+impl OtlpLogsLayer {
+    pub fn new(
+        service_name: &str,
+        endpoint: &str,
+        resource_attrs: &[(String, String)],
+    ) -> Result<Self, RemoteSinkError<tonic::transport::Error>> {
+        let runtime = remote_sinks::runtime()?;
+        // The channel spawns its worker on the current runtime.
+        let channel = tonic::transport::Endpoint::from_shared(endpoint.to_string())
+            .map_err(RemoteSinkError::Client)?
+            .connect_lazy();
+        let client = LogsServiceClient::new(channel);
+
+        let mut attributes = vec![key_value(
+            "service.name",
+            any_value::Value::StringValue(service_name.to_string()),
+        )];
+        for (k, v) in resource_attrs {
+            attributes.push(key_value(k, any_value::Value::StringValue(v.clone())));
+        }
+        let resource = Resource {
+            attributes,
+            ..Default::default()
+        };
+
+        let (tx, rx) = mpsc::channel(MAX_QUEUE_SIZE);
+        remote_sinks::register(&tx);
+        runtime.spawn(export_loop(client, resource, rx));
+
+        Ok(Self { tx })
+    }
+}
+
+impl<S> Layer<S> for OtlpLogsLayer
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+
+        let mut visitor = LogRecordVisitor::default();
+        event.record(&mut visitor);
+
+        let (trace_id, span_id) = ctx
+            .event_span(event)
+            .and_then(|span| otel_ids(&span))
+            .map(|(trace_id, span_id)| (trace_id.to_vec(), span_id.to_vec()))
+            .unwrap_or_default();
+
+        let mut attributes = visitor.attributes;
+        attributes.push(key_value(
+            "target",
+            any_value::Value::StringValue(meta.target().to_string()),
+        ));
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        let record = LogRecord {
+            time_unix_nano: now,
+            observed_time_unix_nano: now,
+            severity_number: severity_number(meta.level()) as i32,
+            severity_text: meta.level().to_string(),
+            body: visitor.body.map(|body| AnyValue {
+                value: Some(any_value::Value::StringValue(body)),
+            }),
+            attributes,
+            trace_id,
+            span_id,
+            ..Default::default()
+        };
+
+        // Drop the record if the exporter can not keep up.
+        let _ = self.tx.try_send(SinkMessage::Item(record));
+    }
+}
+
+async fn export_loop(
+    mut client: LogsServiceClient<tonic::transport::Channel>,
+    resource: Resource,
+    mut rx: mpsc::Receiver<SinkMessage<LogRecord>>,
+) {
+    let mut batch = Vec::with_capacity(MAX_EXPORT_BATCH_SIZE);
+    let mut failures = FailureReporter::new("export logs to otlp collector");
+
+    loop {
+        let mut flushed = None;
+        let closed = match tokio::time::timeout(SCHEDULED_DELAY, rx.recv()).await {
+            Ok(Some(SinkMessage::Flush(done))) => {
+                flushed = Some(done);
+                false
+            }
+            Ok(Some(SinkMessage::Item(record))) => {
+                batch.push(record);
+                if batch.len() < MAX_EXPORT_BATCH_SIZE {
+                    continue;
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(_elapsed) => false,
+        };
+
+        if !batch.is_empty() {
+            let request = ExportLogsServiceRequest {
+                resource_logs: vec![ResourceLogs {
+                    resource: Some(resource.clone()),
+                    instrumentation_library_logs: vec![InstrumentationLibraryLogs {
+                        log_records: std::mem::take(&mut batch),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            };
+
+            if let Err(e) = client.export(request).await {
+                failures.report(e);
+            }
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+
+        if closed {
+            return;
+        }
+    }
+}
+
+fn severity_number(level: &Level) -> SeverityNumber {
+    match *level {
+        Level::TRACE => SeverityNumber::Trace,
+        Level::DEBUG => SeverityNumber::Debug,
+        Level::INFO => SeverityNumber::Info,
+        Level::WARN => SeverityNumber::Warn,
+        Level::ERROR => SeverityNumber::Error,
+    }
+}
+
+fn key_value(key: &str, value: any_value::Value) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+/// Collects the `message` field as body and the others as attributes.
+#[derive(Default)]
+struct LogRecordVisitor {
+    body: Option<String>,
+    attributes: Vec<KeyValue>,
+}
+
+impl LogRecordVisitor {
+    fn push(&mut self, field: &Field, value: any_value::Value) {
+        self.attributes.push(key_value(field.name(), value));
+    }
+}
+
+impl Visit for LogRecordVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, any_value::Value::DoubleValue(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, any_value::Value::IntValue(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, any_value::Value::IntValue(value as i64));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, any_value::Value::BoolValue(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.body = Some(value.to_string());
+        } else {
+            self.push(field, any_value::Value::StringValue(value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_without_runtime() {
+        let layer = OtlpLogsLayer::new("test", "http://127.0.0.1:4317", &[]);
+        assert!(matches!(layer, Err(RemoteSinkError::NoRuntime(_))));
+    }
+}
@@ -13,10 +13,23 @@
 // limitations under the License.
 
 use std::backtrace::Backtrace;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
 use std::panic::PanicInfo;
+use std::path::Path;
+use std::thread;
+use std::time::SystemTime;
 
 use tracing::error;
 
+use crate::event_time::rfc3339;
+use crate::INTERNAL_TARGET;
+
+/// Name of the file written by `install_crash_log`.
+pub const CRASH_LOG_FILE: &str = "crash.log";
+
 pub fn set_panic_hook() {
     // Set a panic hook that records the panic as a `tracing` event at the
     // `ERROR` verbosity level.
@@ -29,6 +42,19 @@ pub fn set_panic_hook() {
     }));
 }
 
+/// Set a panic hook that records the panic as an `ERROR` event, like
+/// `set_panic_hook`, then calls the previously installed hook.
+///
+/// The event reaches all configured sinks, including Sentry, while the
+/// previous hook, e.g. the default one printing to stderr, still runs.
+pub fn install_panic_logger() {
+    let prev = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic| {
+        log_panic(panic);
+        prev(panic);
+    }));
+}
+
 pub fn log_panic(panic: &PanicInfo) {
     let backtrace = Backtrace::force_capture();
     let backtrace = format!("{:?}", backtrace);
@@ -44,3 +70,51 @@ pub fn log_panic(panic: &PanicInfo) {
         error!(message = %panic, backtrace = %backtrace);
     }
 }
+
+/// Set a panic hook appending the panic, with its thread, location and
+/// backtrace, to `crash.log` in `dir`, then calling the previously installed
+/// hook, e.g. the one of `install_panic_logger`.
+///
+/// The file is never rotated, and it is written and synced before the hook
+/// returns, so that the report survives an abort right after the panic, e.g.
+/// with `panic = "abort"`, unlike the lines buffered by the file writers.
+/// Failing to write it is logged as an `ERROR` event of the `common_tracing` target.
+pub fn install_crash_log(dir: impl AsRef<Path>) {
+    let path = dir.as_ref().join(CRASH_LOG_FILE);
+    let prev = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic| {
+        if let Err(e) = write_crash_log(&path, panic) {
+            error!(
+                target: INTERNAL_TARGET,
+                "failed to write the panic to {}: {}",
+                path.display(),
+                e
+            );
+        }
+        prev(panic);
+    }));
+}
+
+fn write_crash_log(path: &Path, panic: &PanicInfo) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let thread = thread::current();
+    let location = match panic.location() {
+        Some(location) => format!("{}:{}:{}", location.file(), location.line(), location.column()),
+        None => "unknown".to_string(),
+    };
+    let report = format!(
+        "{} panic in thread {} at {}: {}\n{:?}\n\n",
+        rfc3339(SystemTime::now()),
+        thread.name().unwrap_or("<unnamed>"),
+        location,
+        panic,
+        Backtrace::force_capture()
+    );
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(report.as_bytes())?;
+    file.sync_all()
+}
@@ -0,0 +1,140 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Wraps a writer of a pipe, e.g. stderr piped into `head`, and silently
+/// discards all output once the reading end is closed(`EPIPE`), instead of
+/// failing on every following line.
+pub struct PipeTolerant<M> {
+    inner: M,
+    closed: AtomicBool,
+}
+
+impl<M> PipeTolerant<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<'a, M> MakeWriter<'a> for PipeTolerant<M>
+where M: MakeWriter<'a>
+{
+    type Writer = PipeTolerantWriter<'a, M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        let inner = if self.closed.load(Ordering::Relaxed) {
+            None
+        } else {
+            Some(self.inner.make_writer())
+        };
+
+        PipeTolerantWriter {
+            inner,
+            closed: &self.closed,
+        }
+    }
+}
+
+pub struct PipeTolerantWriter<'a, W> {
+    /// `None` once the pipe is closed.
+    inner: Option<W>,
+    closed: &'a AtomicBool,
+}
+
+impl<'a, W: Write> PipeTolerantWriter<'a, W> {
+    fn check<T>(&mut self, res: io::Result<T>, discarded: T) -> io::Result<T> {
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed.store(true, Ordering::Relaxed);
+                self.inner = None;
+                Ok(discarded)
+            }
+            res => res,
+        }
+    }
+}
+
+impl<'a, W: Write> Write for PipeTolerantWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = match &mut self.inner {
+            None => return Ok(buf.len()),
+            Some(w) => w.write(buf),
+        };
+        self.check(res, buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let res = match &mut self.inner {
+            None => return Ok(()),
+            Some(w) => w.flush(),
+        };
+        self.check(res, ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use tracing::info;
+
+    use super::*;
+
+    /// A pipe whose reading end is closed, counting the writes.
+    struct ClosedPipe(Arc<AtomicUsize>);
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_closed_pipe_discards_the_records() {
+        let writes = Arc::new(AtomicUsize::new(0));
+        let pipe = {
+            let writes = writes.clone();
+            PipeTolerant::new(move || ClosedPipe(writes.clone()))
+        };
+
+        let mut writer = pipe.make_writer();
+        assert_eq!(writer.write(b"line\n").unwrap(), 5);
+        assert_eq!(writer.write(b"line\n").unwrap(), 5);
+        writer.flush().unwrap();
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+
+        let subscriber = tracing_subscriber::fmt().with_writer(pipe).finish();
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..3 {
+                info!(i, "discarded");
+            }
+        });
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use tracing::level_filters::LevelFilter;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::subscriber::Interest;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::layer::Filter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+use crate::buffer_guard::BoundedWriter;
+use crate::disk_full::DiskFullWriter;
+use crate::logging::new_main_appender;
+use crate::Config;
+
+/// A per-layer `EnvFilter` whose directives can be replaced at runtime.
+#[derive(Clone)]
+pub(crate) struct ReloadableFilter(Arc<RwLock<EnvFilter>>);
+
+impl ReloadableFilter {
+    pub(crate) fn new(directives: &str) -> Self {
+        Self(Arc::new(RwLock::new(EnvFilter::new(directives))))
+    }
+
+    fn set(&self, directives: &str) -> Result<(), ParseError> {
+        let filter = EnvFilter::try_new(directives)?;
+        *self.0.write().unwrap() = filter;
+        // The callsites cached the interest of the previous filter.
+        tracing::callsite::rebuild_interest_cache();
+        Ok(())
+    }
+}
+
+impl<S> Filter<S> for ReloadableFilter
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        Filter::<S>::enabled(&*self.0.read().unwrap(), meta, cx)
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        Filter::<S>::callsite_enabled(&*self.0.read().unwrap(), meta)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Filter::<S>::max_level_hint(&*self.0.read().unwrap())
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_new_span(&*self.0.read().unwrap(), attrs, id, ctx)
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        Filter::<S>::on_record(&*self.0.read().unwrap(), id, values, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_enter(&*self.0.read().unwrap(), id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_exit(&*self.0.read().unwrap(), id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_close(&*self.0.read().unwrap(), id, ctx)
+    }
+}
+
+/// Changes the levels and the file directory of the installed subscriber at
+/// runtime, returned by `init_logging_with_outcome`.
+///
+/// The levels accept `EnvFilter` directives, e.g. `INFO,common_tracing=debug`.
+/// Setting the stderr level overrides `RUST_LOG`.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    file: ReloadableFilter,
+    stderr: ReloadableFilter,
+    /// The config the subscriber was built with.
+    pub(crate) config: Config,
+    /// The prefix and the writer of the main log files, if file logging is on.
+    file_writer: Option<(String, BoundedWriter)>,
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(file: ReloadableFilter, stderr: ReloadableFilter, config: Config) -> Self {
+        Self {
+            file,
+            stderr,
+            config,
+            file_writer: None,
+        }
+    }
+
+    pub(crate) fn with_file_writer(mut self, name: &str, writer: Option<BoundedWriter>) -> Self {
+        self.file_writer = writer.map(|writer| (name.to_string(), writer));
+        self
+    }
+
+    /// Move the main log files to `dir`, e.g. to another volume, rotated and
+    /// cleaned up as before. The sinks of `FileConfig::sinks` are not moved.
+    ///
+    /// The lines buffered for the previous files are written and flushed to
+    /// them first, while new lines are queued for the new files: none is lost.
+    /// `timeout` bounds the wait for the worker, the lines it has not written
+    /// by then go to the new files. Returns whether all of them were written
+    /// to the previous files.
+    ///
+    /// Fails if file logging is off or if the new files can not be created,
+    /// the previous ones are then kept.
+    pub fn set_file_dir(&self, dir: &str, timeout: Duration) -> io::Result<bool> {
+        let (name, writer) = self
+            .file_writer
+            .as_ref()
+            .ok_or_else(|| io::Error::other("file logging is off"))?;
+        let cfg = &self.config.file;
+        fs::create_dir_all(dir)?;
+        let appender = new_main_appender(dir, name, cfg)?;
+        let appender = DiskFullWriter::new(appender, dir, name, cfg.disk_full_policy);
+        writer.swap_appender(appender, timeout)
+    }
+
+    pub fn set_file_level(&self, directives: &str) -> Result<(), ParseError> {
+        self.file.set(directives)
+    }
+
+    pub fn set_stderr_level(&self, directives: &str) -> Result<(), ParseError> {
+        self.stderr.set(directives)
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use tokio::runtime::Handle;
+use tokio::runtime::TryCurrentError;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use crate::FlushTimeout;
+use crate::INTERNAL_TARGET;
+
+/// Min interval between two reports of the failures of a remote sink.
+const FAILURE_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A message to the background task of a remote sink, e.g. the webhook.
+#[cfg_attr(not(any(feature = "http-sink", feature = "otlp-logs")), allow(dead_code))]
+pub(crate) enum SinkMessage<T> {
+    Item(T),
+    /// Send the pending batch now, then notify. It follows the queued items.
+    Flush(oneshot::Sender<()>),
+}
+
+/// A remote sink, flushed by `flush_remote_sinks`.
+trait RemoteSink: Send + Sync {
+    /// Queue a flush request notifying `done`, `None` if the sink is gone.
+    fn flush(&self, done: oneshot::Sender<()>) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>>;
+
+    fn is_gone(&self) -> bool;
+}
+
+impl<T: Send + 'static> RemoteSink for mpsc::WeakSender<SinkMessage<T>> {
+    fn flush(&self, done: oneshot::Sender<()>) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>> {
+        let tx = self.upgrade()?;
+        Some(Box::pin(async move {
+            // Waits for room in the queue instead of dropping the request.
+            let _ = tx.send(SinkMessage::Flush(done)).await;
+        }))
+    }
+
+    fn is_gone(&self) -> bool {
+        self.upgrade().is_none()
+    }
+}
+
+/// The remote sinks created so far. They are held weakly, the task of a sink
+/// stops once its layer is dropped, e.g. by a re-init.
+static REMOTE_SINKS: Lazy<Mutex<Vec<Box<dyn RemoteSink>>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// The runtime running the background task of a remote sink, the current one.
+#[cfg_attr(not(any(feature = "http-sink", feature = "otlp-logs")), allow(dead_code))]
+pub(crate) fn runtime<E>() -> Result<Handle, RemoteSinkError<E>> {
+    Handle::try_current().map_err(RemoteSinkError::NoRuntime)
+}
+
+/// Make the task receiving from `tx` flushed by `flush_remote_sinks`.
+#[cfg_attr(not(any(feature = "http-sink", feature = "otlp-logs")), allow(dead_code))]
+pub(crate) fn register<T: Send + 'static>(tx: &mpsc::Sender<SinkMessage<T>>) {
+    let mut sinks = REMOTE_SINKS.lock().unwrap();
+    sinks.retain(|sink| !sink.is_gone());
+    sinks.push(Box::new(tx.downgrade()));
+}
+
+/// Send the events queued by the remote sinks, waiting at most `timeout`.
+/// Must be called inside a tokio runtime.
+pub(crate) async fn flush_remote_sinks(timeout: Duration) -> Result<(), FlushTimeout> {
+    let requests = {
+        let mut sinks = REMOTE_SINKS.lock().unwrap();
+        sinks.retain(|sink| !sink.is_gone());
+        sinks
+            .iter()
+            .filter_map(|sink| {
+                let (done, flushed) = oneshot::channel();
+                sink.flush(done).map(|request| (request, flushed))
+            })
+            .collect::<Vec<_>>()
+    };
+    if requests.is_empty() {
+        return Ok(());
+    }
+
+    let all = async {
+        let mut waits = Vec::with_capacity(requests.len());
+        for (request, flushed) in requests {
+            request.await;
+            waits.push(flushed);
+        }
+        for flushed in waits {
+            // An error means the task is gone, nothing is left to send.
+            let _ = flushed.await;
+        }
+    };
+    match tokio::time::timeout(timeout, all).await {
+        Ok(_) => Ok(()),
+        Err(_elapsed) => Err(FlushTimeout(timeout)),
+    }
+}
+
+/// A remote sink, e.g. the webhook, can not be created.
+#[cfg_attr(not(any(feature = "http-sink", feature = "otlp-logs")), allow(dead_code))]
+#[derive(Debug)]
+pub enum RemoteSinkError<E> {
+    /// Not called inside a tokio runtime, which runs the background task of the sink.
+    NoRuntime(TryCurrentError),
+    /// The client of the sink can not be created.
+    Client(E),
+}
+
+impl<E: Display> Display for RemoteSinkError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteSinkError::NoRuntime(e) => write!(f, "no tokio runtime to run the sink: {}", e),
+            RemoteSinkError::Client(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+impl<E: Debug + Display> std::error::Error for RemoteSinkError<E> {}
+
+/// Reports the failed requests of a remote sink under `INTERNAL_TARGET`, at
+/// most once per minute with the number of failures not reported since, so
+/// that a dead endpoint does not flood the other outputs.
+///
+/// The report is an event like any other, thus it is also queued to the
+/// failing sink, at most one per minute.
+#[cfg_attr(not(any(feature = "http-sink", feature = "otlp-logs")), allow(dead_code))]
+pub(crate) struct FailureReporter {
+    /// What failed, e.g. `post logs to webhook`.
+    action: &'static str,
+    last_report: Option<Instant>,
+    suppressed: u64,
+}
+
+#[cfg_attr(not(any(feature = "http-sink", feature = "otlp-logs")), allow(dead_code))]
+impl FailureReporter {
+    pub(crate) fn new(action: &'static str) -> Self {
+        Self {
+            action,
+            last_report: None,
+            suppressed: 0,
+        }
+    }
+
+    pub(crate) fn report(&mut self, e: impl Display) {
+        if let Some(last) = self.last_report {
+            if last.elapsed() < FAILURE_REPORT_INTERVAL {
+                self.suppressed += 1;
+                return;
+            }
+        }
+
+        if self.suppressed > 0 {
+            warn!(
+                target: INTERNAL_TARGET,
+                "failed to {}: {}, {} more failures since the last report",
+                self.action,
+                e,
+                self.suppressed
+            );
+        } else {
+            warn!(target: INTERNAL_TARGET, "failed to {}: {}", self.action, e);
+        }
+        self.last_report = Some(Instant::now());
+        self.suppressed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dropped_sinks_are_not_flushed() {
+        // Nobody reads the queue, a flush would time out.
+        let (tx, _rx) = mpsc::channel::<SinkMessage<()>>(1);
+        register(&tx);
+        drop(tx);
+
+        assert_eq!(flush_remote_sinks(Duration::from_millis(100)).await, Ok(()));
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use opentelemetry::sdk::resource::EnvResourceDetector;
+use opentelemetry::sdk::resource::ResourceDetector;
+use opentelemetry::sdk::Resource;
+use opentelemetry::KeyValue;
+
+/// File of the namespace of the pod, mounted with the service account token.
+const K8S_NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// The attributes of the resource found by the detectors: `OTEL_RESOURCE_ATTRIBUTES`
+/// and Kubernetes, see `K8sResourceDetector`. A failing detector adds nothing.
+///
+/// Cloud metadata, e.g. the region, is only available from the metadata HTTP
+/// endpoints of each provider, set it in `OTEL_RESOURCE_ATTRIBUTES` instead.
+pub(crate) fn detected_resource() -> Resource {
+    Resource::from_detectors(
+        Duration::from_secs(0),
+        vec![
+            Box::new(EnvResourceDetector::new()),
+            Box::new(K8sResourceDetector),
+        ],
+    )
+}
+
+/// Detects the pod running the process, from the env vars usually set with
+/// the downward API, in a pod spec:
+///
+/// ```yaml
+/// env:
+///   - name: K8S_POD_NAME
+///     valueFrom: { fieldRef: { fieldPath: metadata.name } }
+///   - name: K8S_NODE_NAME
+///     valueFrom: { fieldRef: { fieldPath: spec.nodeName } }
+/// ```
+///
+/// - `k8s.pod.name`: `K8S_POD_NAME` or `POD_NAME`, then `HOSTNAME`.
+/// - `k8s.pod.uid`: `K8S_POD_UID` or `POD_UID`.
+/// - `k8s.namespace.name`: `K8S_NAMESPACE_NAME` or `POD_NAMESPACE`, then the
+///   namespace of the service account.
+/// - `k8s.node.name`: `K8S_NODE_NAME` or `NODE_NAME`.
+///
+/// Nothing is detected outside of Kubernetes, i.e. without `KUBERNETES_SERVICE_HOST`.
+pub struct K8sResourceDetector;
+
+impl ResourceDetector for K8sResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        if non_empty_env("KUBERNETES_SERVICE_HOST").is_none() {
+            return Resource::empty();
+        }
+
+        let namespace = first_env(&["K8S_NAMESPACE_NAME", "POD_NAMESPACE"]).or_else(|| {
+            fs::read_to_string(K8S_NAMESPACE_FILE)
+                .ok()
+                .map(|ns| ns.trim().to_string())
+                .filter(|ns| !ns.is_empty())
+        });
+        let attrs = [
+            (
+                "k8s.pod.name",
+                first_env(&["K8S_POD_NAME", "POD_NAME", "HOSTNAME"]),
+            ),
+            ("k8s.pod.uid", first_env(&["K8S_POD_UID", "POD_UID"])),
+            ("k8s.namespace.name", namespace),
+            ("k8s.node.name", first_env(&["K8S_NODE_NAME", "NODE_NAME"])),
+        ];
+
+        Resource::new(
+            attrs
+                .into_iter()
+                .filter_map(|(k, v)| v.map(|v| KeyValue::new(k, v))),
+        )
+    }
+}
+
+fn first_env(keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| non_empty_env(key))
+}
+
+fn non_empty_env(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty())
+}
@@ -0,0 +1,75 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use opentelemetry::sdk::trace::Tracer;
+use opentelemetry::trace::TraceContextExt;
+use tracing::level_filters::LevelFilter;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_opentelemetry::OtelData;
+use tracing_opentelemetry::PreSampledTracer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::layer::Filter;
+use tracing_subscriber::registry::LookupSpan;
+
+/// A per-layer filter makes logging follow the trace sampling decision:
+/// events inside a span whose trace is not sampled are only enabled up to
+/// `unsampled_level`, e.g. `WARN` keeps the warnings and errors only.
+///
+/// Events outside any span, or in spans not tracked by the OpenTelemetry
+/// layer, are not affected. Without a tracer this filter enables everything.
+pub struct SampledFilter {
+    tracer: Option<Tracer>,
+    unsampled_level: LevelFilter,
+}
+
+impl SampledFilter {
+    pub fn new(tracer: Option<Tracer>, unsampled_level: LevelFilter) -> Self {
+        Self {
+            tracer,
+            unsampled_level,
+        }
+    }
+}
+
+impl<S> Filter<S> for SampledFilter
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        let tracer = match &self.tracer {
+            None => return true,
+            Some(tracer) => tracer,
+        };
+
+        if !meta.is_event() || self.unsampled_level >= *meta.level() {
+            return true;
+        }
+
+        let span = match cx.lookup_current() {
+            None => return true,
+            Some(span) => span,
+        };
+
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<OtelData>() {
+            None => true,
+            // Makes the sampling decision if not made yet, it is cached in the span builder.
+            Some(data) => tracer
+                .sampled_context(data)
+                .span()
+                .span_context()
+                .is_sampled(),
+        }
+    }
+}
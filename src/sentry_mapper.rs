@@ -0,0 +1,78 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use sentry_tracing::breadcrumb_from_event;
+use sentry_tracing::event_from_event;
+use sentry_tracing::EventMapping;
+use serde_json::Value;
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::SentryConfig;
+
+/// Map the events to sentry as `sentry_tracing` does, `ERROR` and `WARN` ones
+/// to events and the others to breadcrumbs, then cut their message and
+/// fields, and strip the fields, as configured by `cfg`.
+pub(crate) fn event_mapper<S>(
+    cfg: &SentryConfig,
+) -> impl Fn(&Event<'_>, Context<'_, S>) -> EventMapping + Send + Sync + 'static
+where S: Subscriber + for<'a> LookupSpan<'a> {
+    let cfg = cfg.clone();
+    move |event: &Event<'_>, ctx: Context<'_, S>| match *event.metadata().level() {
+        Level::ERROR | Level::WARN => {
+            let mut sentry_event = event_from_event(event, ctx);
+            if let Some(message) = &mut sentry_event.message {
+                truncate(message, cfg.max_message_len);
+            }
+            trim_fields(sentry_event.extra.iter_mut(), cfg.max_field_len);
+            sentry_event
+                .extra
+                .retain(|k, _| !cfg.strip_fields.contains(k));
+            EventMapping::Event(sentry_event)
+        }
+        _ => {
+            let mut breadcrumb = breadcrumb_from_event(event);
+            if let Some(message) = &mut breadcrumb.message {
+                truncate(message, cfg.max_message_len);
+            }
+            trim_fields(breadcrumb.data.iter_mut(), cfg.max_field_len);
+            breadcrumb.data.retain(|k, _| !cfg.strip_fields.contains(k));
+            EventMapping::Breadcrumb(breadcrumb)
+        }
+    }
+}
+
+/// Cut the string values of the fields to `max` bytes, 0 means no limit.
+fn trim_fields<'a>(fields: impl Iterator<Item = (&'a String, &'a mut Value)>, max: usize) {
+    for (_, value) in fields {
+        if let Value::String(s) = value {
+            truncate(s, max);
+        }
+    }
+}
+
+/// Cut `s` to at most `max` bytes at a char boundary, 0 means no limit.
+fn truncate(s: &mut String, max: usize) {
+    if max == 0 || s.len() <= max {
+        return;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+}
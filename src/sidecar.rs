@@ -0,0 +1,172 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+use std::io;
+use std::io::Write;
+
+use time::OffsetDateTime;
+use tracing::Metadata;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::file_appender::LocalRollingAppender;
+
+/// First byte of the tagged lines of a log, followed by the unix seconds of
+/// the line and a space.
+const LOG_TAG: u8 = 0x1d;
+/// First byte of the tagged lines of its JSON sidecar.
+const SIDECAR_TAG: u8 = 0x1e;
+
+thread_local! {
+    /// Unix seconds of the last log line tagged on this thread.
+    static LINE_TIME: Cell<i64> = const { Cell::new(0) };
+}
+
+/// Wraps the writer of a log with a JSON sidecar, tagging the lines with
+/// their file and time for the `SidecarAppender`.
+///
+/// The sidecar lines take the time of the last log line tagged on the same
+/// thread, the one of the same event: the layer writing the log line has to be
+/// stacked before the one writing the sidecar line.
+#[derive(Clone)]
+pub(crate) struct SidecarTagWriter<W> {
+    inner: W,
+    tag: Option<u8>,
+}
+
+impl<W> SidecarTagWriter<W> {
+    /// The writer of the log lines, passing them through untagged unless `on`,
+    /// i.e. the log has no sidecar.
+    pub fn log(inner: W, on: bool) -> Self {
+        Self {
+            inner,
+            tag: on.then_some(LOG_TAG),
+        }
+    }
+
+    pub fn sidecar(inner: W) -> Self {
+        Self {
+            inner,
+            tag: Some(SIDECAR_TAG),
+        }
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let tag = match self.tag {
+            None => return vec![],
+            Some(tag) => tag,
+        };
+        let time = if tag == SIDECAR_TAG {
+            LINE_TIME.with(Cell::get)
+        } else {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            LINE_TIME.with(|time| time.set(now));
+            now
+        };
+        format!("{}{} ", tag as char, time).into_bytes()
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for SidecarTagWriter<W> {
+    type Writer = TaggedWriter<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        TaggedWriter {
+            inner: self.inner.make_writer(),
+            header: self.header(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        TaggedWriter {
+            inner: self.inner.make_writer_for(meta),
+            header: self.header(),
+        }
+    }
+}
+
+pub(crate) struct TaggedWriter<W> {
+    inner: W,
+    header: Vec<u8>,
+}
+
+impl<W: Write> Write for TaggedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.header.is_empty() {
+            return self.inner.write(buf);
+        }
+
+        // The formatters write a line at once, the header is sent with it.
+        let mut line = Vec::with_capacity(self.header.len() + buf.len());
+        line.extend_from_slice(&self.header);
+        line.extend_from_slice(buf);
+        self.inner.write_all(&line)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the lines tagged by a `SidecarTagWriter` to the files of a log or
+/// of its JSON sidecar, each in the file of the period of its tagged time.
+///
+/// The lines of an event are thus always in files of the same period, even if
+/// a rotation happens in between. Untagged lines are written to the log.
+#[derive(Debug)]
+pub struct SidecarAppender {
+    log: LocalRollingAppender,
+    sidecar: LocalRollingAppender,
+}
+
+impl SidecarAppender {
+    /// Both appenders should have the same rotation.
+    pub fn new(log: LocalRollingAppender, sidecar: LocalRollingAppender) -> Self {
+        Self { log, sidecar }
+    }
+}
+
+/// The tag, time and line of a tagged line.
+fn parse_tagged(buf: &[u8]) -> Option<(u8, OffsetDateTime, &[u8])> {
+    let tag = *buf.first()?;
+    if tag != LOG_TAG && tag != SIDECAR_TAG {
+        return None;
+    }
+    let end = buf.iter().take(24).position(|b| *b == b' ')?;
+    let secs = std::str::from_utf8(&buf[1..end]).ok()?.parse::<i64>().ok()?;
+    let time = OffsetDateTime::from_unix_timestamp(secs).ok()?;
+    Some((tag, time, &buf[end + 1..]))
+}
+
+impl Write for SidecarAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (tag, time, line) = match parse_tagged(buf) {
+            Some(tagged) => tagged,
+            None => return self.log.write(buf),
+        };
+        let appender = if tag == SIDECAR_TAG {
+            &mut self.sidecar
+        } else {
+            &mut self.log
+        };
+        appender.write_at(time, line)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.log.flush()?;
+        self.sidecar.flush()
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use tracing_appender::non_blocking::WorkerGuard;
+
+use crate::buffered_log_bytes;
+use crate::summary::shutdown_with_guards;
+use crate::FlushTimeout;
+
+/// Wait at most `timeout` for the background workers to write the buffered
+/// lines to the files, from a signal handler, e.g. for `SIGSEGV` or `SIGABRT`
+/// before the process dies. Returns whether all the lines were written.
+///
+/// Flushing a `WorkerGuard` or the trace exporters is not async-signal-safe:
+/// they lock, allocate and join threads. This only reads `buffered_log_bytes`
+/// and sleeps, the workers keep writing in their own threads meanwhile. Thus:
+///
+/// - Lines emitted after the signal, e.g. by the handler, may not be waited for.
+/// - Nothing is written if the workers are stuck, e.g. on a full disk with
+///   `DiskFullPolicy::Block`, or if the signal stops all threads.
+/// - Spans are lost, and lines are written but not synced to disk.
+///
+/// `FileConfig::sync_error_flush` writes the `ERROR` lines before a crash too.
+/// For `SIGTERM`, shut down outside of the signal handler, see `flush_on_termination`.
+pub fn try_flush_from_signal(timeout: Duration) -> bool {
+    let started = Instant::now();
+    loop {
+        if buffered_log_bytes() == 0 {
+            return true;
+        }
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Wait for `SIGTERM`, or Ctrl-C, then log the shutdown summary, flush the
+/// traces and the remote sinks and drop `guards`, the guards returned by
+/// `init_logging`, so that every line reaches the files, in the order of
+/// `shutdown_logging`. Exit the process once it returns, e.g.
+///
+/// `tokio::spawn(async move { let _ = flush_on_termination(guards, timeout).await; std::process::exit(0) })`
+///
+/// `timeout` bounds the flush of the traces, see `shutdown_logging`; dropping
+/// the guards waits for the workers to write the buffered lines.
+pub async fn flush_on_termination(
+    guards: Vec<WorkerGuard>,
+    timeout: Duration,
+) -> Result<(), FlushTimeout> {
+    wait_for_termination().await;
+    shutdown_with_guards(guards, timeout).await
+}
+
+#[cfg(unix)]
+async fn wait_for_termination() {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_termination() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[cfg(unix)]
+    use tokio::signal::unix::SignalKind;
+    use tracing_appender::non_blocking::NonBlockingBuilder;
+    use tracing_appender::rolling;
+
+    use super::*;
+    use crate::buffer_guard::BoundedWriter;
+    use crate::buffer_guard::ReleaseOnWrite;
+    use crate::buffer_guard::SharedAppender;
+    use crate::disk_full::DiskFullWriter;
+    use crate::file_appender::FileAppender;
+    use crate::DiskFullPolicy;
+
+    /// Blocks the worker writing to it while the lock is held.
+    #[derive(Clone, Default)]
+    struct Gate(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Gate {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_try_flush_from_signal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let appender = FileAppender::Utc(rolling::never(path, "signal"));
+        let appender = SharedAppender::new(DiskFullWriter::new(
+            appender,
+            path,
+            "signal",
+            DiskFullPolicy::default(),
+        ));
+
+        let gate = Gate::default();
+        let closed = gate.0.lock().unwrap();
+        let (inner, _guard) = NonBlockingBuilder::default().finish(ReleaseOnWrite(gate.clone()));
+        let mut writer = BoundedWriter::new(inner, appender, 0);
+        writer.write_all(b"buffered\n").unwrap();
+
+        // The worker is stuck on the gate, the line stays buffered.
+        assert!(!try_flush_from_signal(Duration::from_millis(50)));
+
+        drop(closed);
+        assert!(try_flush_from_signal(Duration::from_secs(10)));
+        assert_eq!(gate.0.lock().unwrap().as_slice(), b"buffered\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_flush_on_termination() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut writer, guard) =
+            tracing_appender::non_blocking(rolling::never(dir.path(), "term"));
+        writer.write_all(b"last words\n").unwrap();
+
+        // Installs the handler of the process, SIGTERM no longer kills it.
+        let _sigterm = tokio::signal::unix::signal(SignalKind::terminate()).unwrap();
+        let mut flushed = tokio::spawn(flush_on_termination(vec![guard], Duration::from_secs(5)));
+        // The task may not listen yet when the first signal is sent.
+        let result = loop {
+            let status = std::process::Command::new("kill")
+                .args(["-TERM", &std::process::id().to_string()])
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let waited = tokio::time::timeout(Duration::from_millis(100), &mut flushed).await;
+            if let Ok(result) = waited {
+                break result.unwrap();
+            }
+        };
+        assert_eq!(result, Ok(()));
+
+        let content = std::fs::read_to_string(dir.path().join("term")).unwrap();
+        assert_eq!(content, "last words\n");
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+
+use tracing::level_filters::LevelFilter;
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+thread_local! {
+    /// The most verbose level enabled on this thread, `None` if not silenced.
+    static MAX_LEVEL: Cell<Option<LevelFilter>> = const { Cell::new(None) };
+}
+
+/// Silences the events of the current thread more verbose than `max_level`
+/// while it is alive, e.g. `SilenceGuard::new(LevelFilter::WARN)` during a
+/// noisy bulk operation. Other threads are not affected.
+///
+/// Nested guards can only silence more, the previous level is restored on drop.
+/// Do not hold a guard across an `.await`: the task may move to another thread,
+/// while other tasks on this one would be silenced.
+pub struct SilenceGuard {
+    previous: Option<LevelFilter>,
+}
+
+impl SilenceGuard {
+    pub fn new(max_level: LevelFilter) -> Self {
+        let previous = MAX_LEVEL.with(|cell| {
+            let previous = cell.get();
+            // More verbose levels are greater.
+            cell.set(Some(previous.map_or(max_level, |p| p.min(max_level))));
+            previous
+        });
+        Self { previous }
+    }
+}
+
+impl Drop for SilenceGuard {
+    fn drop(&mut self) {
+        MAX_LEVEL.with(|cell| cell.set(self.previous));
+    }
+}
+
+/// Run `f` with the events of the current thread more verbose than
+/// `max_level` silenced, see `SilenceGuard`.
+pub fn with_log_level_scope<R>(max_level: LevelFilter, f: impl FnOnce() -> R) -> R {
+    let _guard = SilenceGuard::new(max_level);
+    f()
+}
+
+/// A layer dropping the events silenced by a `SilenceGuard`, for every layer.
+pub(crate) struct SilenceLayer;
+
+impl<S: Subscriber> Layer<S> for SilenceLayer {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        match MAX_LEVEL.with(|cell| cell.get()) {
+            None => true,
+            Some(max_level) => event.metadata().level() <= &max_level,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use slog::Drain;
+use slog::Key;
+use slog::Never;
+use slog::OwnedKVList;
+use slog::Record;
+use slog::Serializer;
+use slog::KV;
+
+use crate::logfmt::write_value;
+
+/// Target of the events forwarded from `slog`.
+pub const SLOG_TARGET: &str = "slog";
+
+/// A `slog` drain forwarding the records to `tracing`, like `LogTracer` does
+/// for the `log` crate, to unify the output while migrating, e.g.
+///
+/// `let logger = slog::Logger::root(SlogDrain, slog::o!("component" => "raft"));`
+///
+/// Every record becomes an event of target [`SLOG_TARGET`] with the record
+/// message, `CRITICAL` records are `ERROR` events. `tracing` fields are known
+/// at compile time, thus the key-value pairs of the record and of the logger
+/// are written in one `slog.kv` field as logfmt pairs, record ones first, e.g.
+/// `term=3 component=raft`, and the module, file and line of the record in
+/// `slog.module_path`, `slog.file` and `slog.line`.
+///
+/// Filter the events with the `slog` target, e.g. `RUST_LOG=slog=warn`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SlogDrain;
+
+macro_rules! forward {
+    ($level:expr, $record:expr, $kv:expr) => {
+        tracing::event!(
+            target: SLOG_TARGET,
+            $level,
+            slog.module_path = $record.module(),
+            slog.file = $record.file(),
+            slog.line = $record.line(),
+            slog.kv = $kv.as_str(),
+            "{}",
+            $record.msg()
+        )
+    };
+}
+
+impl Drain for SlogDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record<'_>, values: &OwnedKVList) -> Result<(), Never> {
+        // The pairs of the record first, then the ones of the logger.
+        let mut kv = Pairs(String::new());
+        let _ = record.kv().serialize(record, &mut kv);
+        let _ = values.serialize(record, &mut kv);
+        let kv = kv.0;
+
+        match record.level() {
+            slog::Level::Critical | slog::Level::Error => {
+                forward!(tracing::Level::ERROR, record, kv)
+            }
+            slog::Level::Warning => forward!(tracing::Level::WARN, record, kv),
+            slog::Level::Info => forward!(tracing::Level::INFO, record, kv),
+            slog::Level::Debug => forward!(tracing::Level::DEBUG, record, kv),
+            slog::Level::Trace => forward!(tracing::Level::TRACE, record, kv),
+        }
+        Ok(())
+    }
+}
+
+/// Collects the key-value pairs as logfmt pairs.
+struct Pairs(String);
+
+impl Serializer for Pairs {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments<'_>) -> slog::Result {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        self.0.push_str(&key.to_string());
+        self.0.push('=');
+        write_value(&mut self.0, &val.to_string(), ' ');
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use tracing::field::Field;
+    use tracing::field::Visit;
+    use tracing::Event;
+    use tracing::Level;
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::Context;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Layer;
+
+    use super::*;
+
+    /// The level, target and fields of the captured events.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(Level, String, BTreeMap<String, String>)>>>);
+
+    struct Fields(BTreeMap<String, String>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let mut fields = Fields(BTreeMap::new());
+            event.record(&mut fields);
+            let meta = event.metadata();
+            let captured = (*meta.level(), meta.target().to_string(), fields.0);
+            self.0.lock().unwrap().push(captured);
+        }
+    }
+
+    #[test]
+    fn test_slog_records_become_events() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let logger = slog::Logger::root(SlogDrain, slog::o!("component" => "raft"));
+            slog::info!(logger, "elected"; "term" => 3);
+            slog::crit!(logger, "lost quorum");
+        });
+
+        let events = capture.0.lock().unwrap();
+        assert_eq!(events.len(), 2);
+
+        let (level, target, fields) = &events[0];
+        assert_eq!(*level, Level::INFO);
+        assert_eq!(target, SLOG_TARGET);
+        assert_eq!(fields["message"], "elected");
+        assert_eq!(fields["slog.kv"], "term=3 component=raft");
+        assert_eq!(fields["slog.module_path"], module_path!());
+
+        let (level, _, fields) = &events[1];
+        assert_eq!(*level, Level::ERROR);
+        assert_eq!(fields["message"], "lost quorum");
+        assert_eq!(fields["slog.kv"], "component=raft");
+    }
+}
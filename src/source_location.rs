@@ -0,0 +1,117 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::RefCell;
+
+use serde_json::Map;
+use serde_json::Value;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::Event;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::SourceLocation;
+
+thread_local! {
+    /// The location of the event or span being written on this thread.
+    static CURRENT: RefCell<Option<Location>> = const { RefCell::new(None) };
+}
+
+/// Where an event or a span is in the source.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Location {
+    file: Option<String>,
+    line: Option<u32>,
+    module_path: Option<String>,
+}
+
+impl Location {
+    pub fn of(meta: &Metadata<'_>) -> Self {
+        Self {
+            file: meta.file().map(|f| f.to_string()),
+            line: meta.line(),
+            module_path: meta.module_path().map(|m| m.to_string()),
+        }
+    }
+}
+
+/// Members of the location written by the JSON formatters, `filename` and
+/// `line_number` by `tracing_subscriber`.
+const LOCATION_MEMBERS: [&str; 5] = ["file", "line", "module_path", "filename", "line_number"];
+
+/// Add or remove the location members of a JSON `record` as configured by `mode`.
+pub(crate) fn apply(record: &mut Map<String, Value>, mode: SourceLocation, location: &Location) {
+    match mode {
+        SourceLocation::Auto => {}
+        SourceLocation::Include => {
+            if let Some(file) = &location.file {
+                record.insert("file".to_string(), Value::from(file.as_str()));
+            }
+            if let Some(line) = location.line {
+                record.insert("line".to_string(), Value::from(line));
+            }
+            if let Some(module_path) = &location.module_path {
+                record.insert("module_path".to_string(), Value::from(module_path.as_str()));
+            }
+        }
+        SourceLocation::Exclude => {
+            for member in LOCATION_MEMBERS {
+                record.remove(member);
+            }
+        }
+    }
+}
+
+/// The location kept by `CaptureLocation` for the record being written.
+pub(crate) fn current() -> Location {
+    CURRENT.with(|current| current.borrow().clone().unwrap_or_default())
+}
+
+/// Keeps the location of every event and span on the current thread, for a
+/// writer behind a formatter not passing the metadata, e.g. Bunyan. Must be
+/// stacked before the formatting layer.
+pub(crate) struct CaptureLocation;
+
+impl CaptureLocation {
+    fn set(meta: &Metadata<'_>) {
+        CURRENT.with(|current| *current.borrow_mut() = Some(Location::of(meta)));
+    }
+}
+
+impl<S> Layer<S> for CaptureLocation
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        Self::set(attrs.metadata());
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // The records of the `log` crate have their location in fields.
+        match event.normalized_metadata() {
+            Some(meta) => Self::set(&meta),
+            None => Self::set(event.metadata()),
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            Self::set(span.metadata());
+        }
+    }
+}
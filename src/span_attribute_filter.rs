@@ -0,0 +1,59 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+
+use tracing::span::Id;
+use tracing::Subscriber;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Drops span attributes not in the allowlist right before the span is
+/// exported by the OpenTelemetry layer.
+///
+/// It only changes what is exported, the span fields are still visible to
+/// the logging layers. Must be placed before the OpenTelemetry layer, e.g.
+/// `allowlist.and_then(tracing_opentelemetry::layer())`, so that its
+/// `on_close` runs first.
+pub struct SpanAttributeAllowlist {
+    allowed: HashSet<String>,
+}
+
+impl SpanAttributeAllowlist {
+    pub fn new(allowed: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            allowed: allowed.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<S> Layer<S> for SpanAttributeAllowlist
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            None => return,
+            Some(span) => span,
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<OtelData>() {
+            if let Some(attributes) = data.builder.attributes.as_mut() {
+                attributes.retain(|key, _| self.allowed.contains(key.as_str()));
+            }
+        }
+    }
+}
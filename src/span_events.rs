@@ -0,0 +1,166 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::io::Write;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use serde_json::Map;
+use serde_json::Value;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::SpanRef;
+use tracing_subscriber::Layer;
+
+use crate::event_time::rfc3339;
+
+/// Writes a `span_open` JSON record when a span is created and a `span_close`
+/// one when it closes, one per line, to rebuild the span tree offline:
+///
+/// `{"type":"span_open","time":"2022-10-01T08:00:00.000000Z","span_id":2,"parent_id":1,"name":"query","target":"databend_query::sessions","fields":{"query_id":"abc"}}`
+///
+/// `parent_id` is `null` for a root span. The close record also has the
+/// fields recorded after the span was created, and `duration_ms`, the time
+/// the span was open.
+///
+/// Ids are the `tracing` span ids: unique among the open spans only, the id
+/// of a closed span may be reused. Pair the records in file order.
+pub struct SpanEventsLayer<W> {
+    writer: W,
+}
+
+impl<W> SpanEventsLayer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+/// The fields and the open time of a span, kept for its close record.
+struct SpanRecord {
+    fields: Map<String, Value>,
+    opened: Instant,
+}
+
+impl<W> SpanEventsLayer<W>
+where W: for<'w> MakeWriter<'w> + 'static
+{
+    /// Write the record of `span`, with its `duration_ms` if it was `opened`.
+    fn write<S>(
+        &self,
+        kind: &str,
+        span: &SpanRef<'_, S>,
+        fields: &Map<String, Value>,
+        opened: Option<Instant>,
+    ) where
+        S: for<'a> LookupSpan<'a>,
+    {
+        let meta = span.metadata();
+
+        let mut record = Map::new();
+        record.insert("type".to_string(), Value::from(kind));
+        record.insert("time".to_string(), Value::from(rfc3339(SystemTime::now())));
+        record.insert("span_id".to_string(), Value::from(span.id().into_u64()));
+        let parent_id = span.parent().map(|parent| parent.id().into_u64());
+        record.insert("parent_id".to_string(), Value::from(parent_id));
+        record.insert("name".to_string(), Value::from(meta.name()));
+        record.insert("target".to_string(), Value::from(meta.target()));
+        record.insert("fields".to_string(), Value::Object(fields.clone()));
+        if let Some(opened) = opened {
+            let ms = opened.elapsed().as_millis() as u64;
+            record.insert("duration_ms".to_string(), Value::from(ms));
+        }
+
+        if let Ok(mut line) = serde_json::to_vec(&record) {
+            line.push(b'\n');
+            let _ = self.writer.make_writer().write_all(&line);
+        }
+    }
+}
+
+impl<S, W> Layer<S> for SpanEventsLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        self.write("span_open", &span, &fields, None);
+        span.extensions_mut().insert(SpanRecord {
+            fields,
+            opened: Instant::now(),
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(record) = span.extensions_mut().get_mut::<SpanRecord>() {
+                values.record(&mut JsonVisitor(&mut record.fields));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let record = span.extensions_mut().remove::<SpanRecord>();
+        if let Some(record) = record {
+            self.write("span_close", &span, &record.fields, Some(record.opened));
+        }
+    }
+}
+
+/// Records the fields as JSON values, numbers and booleans as such.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'a> Visit for JsonVisitor<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let value = format!("{:?}", value);
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::level_filters::LevelFilter;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::Level;
+use tracing::Metadata;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::layer::Filter;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The level a span raised the filter to, stored in the span extensions.
+struct SpanLevel(Level);
+
+/// A per-layer filter enabling events of a more verbose level inside the
+/// spans that ask for it with a field, e.g. with `field` = `log_level`:
+///
+/// ```ignore
+/// let span = tracing::info_span!("request", tenant = "t1", log_level = "debug");
+/// ```
+///
+/// All events within the span and its children, down to `DEBUG`, are enabled.
+/// It is meant to be combined with the regular filter: `env_filter.or(span_level)`.
+///
+/// Note that it disables the global max level optimization of the layer it
+/// filters once enabled: every callsite has to be checked against the current span.
+pub struct SpanLevelFilter {
+    field: String,
+}
+
+impl SpanLevelFilter {
+    /// Create the filter looking for `field` on spans, an empty `field` disables it.
+    pub fn new(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+        }
+    }
+}
+
+impl<S> Filter<S> for SpanLevelFilter
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        if self.field.is_empty() {
+            return false;
+        }
+
+        let span = match cx.lookup_current() {
+            None => return false,
+            Some(span) => span,
+        };
+
+        for span in span.scope() {
+            if let Some(level) = span.extensions().get::<SpanLevel>() {
+                return meta.level() <= &level.0;
+            }
+        }
+        false
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        if self.field.is_empty() {
+            Some(LevelFilter::OFF)
+        } else {
+            None
+        }
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if self.field.is_empty() {
+            return;
+        }
+
+        let mut visitor = LevelVisitor {
+            field: &self.field,
+            level: None,
+        };
+        attrs.record(&mut visitor);
+
+        if let (Some(level), Some(span)) = (visitor.level, ctx.span(id)) {
+            span.extensions_mut().replace(SpanLevel(level));
+        }
+    }
+}
+
+struct LevelVisitor<'a> {
+    field: &'a str,
+    level: Option<Level>,
+}
+
+impl<'a> Visit for LevelVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.field {
+            self.level = Level::from_str(value).ok();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == self.field {
+            self.level = Level::from_str(format!("{:?}", value).trim_matches('"')).ok();
+        }
+    }
+}
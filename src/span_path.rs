@@ -0,0 +1,77 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::RefCell;
+
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::Scope;
+use tracing_subscriber::Layer;
+
+/// Name of the member with the span path of a JSON record.
+pub(crate) const SPAN_PATH_FIELD: &str = "span_path";
+
+thread_local! {
+    /// The span path of the event or span being written on this thread.
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The names of the spans of `scope` from the root, joined with `:`,
+/// e.g. `server:handle_request:query`.
+pub(crate) fn join<'a, R: LookupSpan<'a>>(scope: Scope<'a, R>) -> String {
+    scope
+        .from_root()
+        .map(|span| span.name())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The span path kept by `CaptureSpanPath` for the record being written,
+/// `None` outside of any span.
+pub(crate) fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Keeps the span path of every event and span on the current thread, for a
+/// writer behind a formatter not passing the context, e.g. Bunyan. Must be
+/// stacked before the formatting layer.
+///
+/// The path of a span ends with the span itself.
+pub(crate) struct CaptureSpanPath;
+
+impl CaptureSpanPath {
+    fn set(path: Option<String>) {
+        CURRENT.with(|current| *current.borrow_mut() = path);
+    }
+}
+
+impl<S> Layer<S> for CaptureSpanPath
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        Self::set(ctx.span_scope(id).map(join));
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        Self::set(ctx.event_scope(event).map(join));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        Self::set(ctx.span_scope(&id).map(join));
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Target of the events reporting the timings of closed spans.
+pub const SPAN_TIMING_TARGET: &str = "common_tracing::span_timing";
+
+/// Logs the busy and idle time of every span when it closes, a lightweight
+/// alternative to tokio-console for spotting spans waiting on `.await`.
+///
+/// A span is busy while entered, e.g. while the future it instruments is
+/// polled, and idle otherwise. On close an `INFO` event is emitted with:
+///
+/// - target set to [`SPAN_TIMING_TARGET`];
+/// - the name and target of the closed span in `span` and `span_target`;
+/// - the durations in `busy_ms` and `idle_ms`.
+///
+/// Like [`crate::TargetLevelOverrides`], emitting only works with the global
+/// default subscriber, scoped subscribers drop events dispatched from within a layer.
+pub struct SpanTimingLayer;
+
+struct Timings {
+    busy: Duration,
+    idle: Duration,
+    last: Instant,
+}
+
+impl Timings {
+    fn new() -> Self {
+        Self {
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+}
+
+impl<S> Layer<S> for SpanTimingLayer
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timings::new());
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+                let now = Instant::now();
+                timings.idle += now - timings.last;
+                timings.last = now;
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+                let now = Instant::now();
+                timings.busy += now - timings.last;
+                timings.last = now;
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            None => return,
+            Some(span) => span,
+        };
+        let (busy, idle) = match span.extensions().get::<Timings>() {
+            None => return,
+            Some(timings) => (timings.busy, timings.idle + timings.last.elapsed()),
+        };
+
+        let meta = span.metadata();
+        tracing::info!(
+            target: SPAN_TIMING_TARGET,
+            span = meta.name(),
+            span_target = meta.target(),
+            busy_ms = busy.as_millis() as u64,
+            idle_ms = idle.as_millis() as u64,
+            "span closed"
+        );
+    }
+}
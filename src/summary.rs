@@ -0,0 +1,213 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::default_span;
+use crate::disk_full_incidents;
+use crate::dropped_log_lines;
+use crate::flush_traces;
+use crate::remote_sinks::flush_remote_sinks;
+use crate::trace_export_failures;
+use crate::FlushTimeout;
+use crate::INTERNAL_TARGET;
+
+/// Events counted by level, `ERROR` first.
+static EVENTS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Log a summary of the run, flush the traces then the remote sinks, waiting
+/// at most `timeout` for each flush, see `flush_traces`.
+///
+/// The shutdown runs in this order:
+///
+/// 1. the summary is logged and the default span, if any, is closed;
+/// 2. the traces are exported, e.g. to jaeger, with the spans just closed;
+/// 3. the events queued by the remote sinks, the webhook and the OTLP logs,
+///    are sent, with the diagnostics of the trace flush;
+/// 4. the files and stderr are flushed by the caller, dropping the guards,
+///    see `InitOutcome::shutdown` and `flush_on_termination` doing it all.
+///
+/// Call it during graceful shutdown, before the guards returned by
+/// `init_logging` are dropped, so that the summary reaches the files:
+///
+/// `shutdown summary events_error=0 events_warn=3 events_info=1024 events_debug=0 events_trace=0 dropped_lines=0 disk_full_incidents=0 trace_export_failures=0`
+///
+/// The default span, if any, is closed: the events emitted afterwards have
+/// no span, see `Config::default_span`.
+///
+/// Events are counted at the file level. The number of exported spans is not
+/// known, the exporters do not report it, only the failures are.
+pub async fn shutdown_logging(timeout: Duration) -> Result<(), FlushTimeout> {
+    let count = |i: usize| EVENTS[i].load(Ordering::Relaxed);
+    tracing::info!(
+        target: INTERNAL_TARGET,
+        events_error = count(0),
+        events_warn = count(1),
+        events_info = count(2),
+        events_debug = count(3),
+        events_trace = count(4),
+        dropped_lines = dropped_log_lines(),
+        disk_full_incidents = disk_full_incidents(),
+        trace_export_failures = trace_export_failures(),
+        "shutdown summary"
+    );
+
+    // Closed before the flush, so that it is exported.
+    default_span::close();
+    let traces = flush_traces(timeout).await;
+    let remote = flush_remote_sinks(timeout).await;
+    traces.and(remote)
+}
+
+/// Shut down as `shutdown_logging` does, then drop `guards`, waiting for the
+/// workers to write the buffered lines to the files, and flush stdout and stderr.
+pub(crate) async fn shutdown_with_guards(
+    guards: Vec<WorkerGuard>,
+    timeout: Duration,
+) -> Result<(), FlushTimeout> {
+    let flushed = shutdown_logging(timeout).await;
+    drop(guards);
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    flushed
+}
+
+/// A layer counting the events by level for the shutdown summary.
+pub(crate) struct EventCountsLayer;
+
+impl<S: Subscriber> Layer<S> for EventCountsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let i = match *event.metadata().level() {
+            Level::ERROR => 0,
+            Level::WARN => 1,
+            Level::INFO => 2,
+            Level::DEBUG => 3,
+            _ => 4,
+        };
+        EVENTS[i].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use opentelemetry::sdk::export::trace::SpanData;
+    use opentelemetry::sdk::trace::Span;
+    use opentelemetry::sdk::trace::SpanProcessor;
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::TraceResult;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::Context;
+    use tokio::sync::mpsc;
+    use tracing_appender::non_blocking::NonBlockingBuilder;
+
+    use super::*;
+    use crate::remote_sinks;
+    use crate::remote_sinks::SinkMessage;
+    use crate::tracer::register_provider;
+
+    /// The outputs in the order they were flushed.
+    #[derive(Clone, Debug, Default)]
+    struct Flushed(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Flushed {
+        fn push(&self, output: &'static str) {
+            self.0.lock().unwrap().push(output);
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockProcessor(Flushed);
+
+    impl SpanProcessor for MockProcessor {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, _span: SpanData) {}
+
+        fn force_flush(&self) -> TraceResult<()> {
+            self.0.push("traces");
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    struct MockFile(Flushed);
+
+    impl Write for MockFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.push("files");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_order() {
+        let flushed = Flushed::default();
+
+        let provider = TracerProvider::builder()
+            .with_span_processor(MockProcessor(flushed.clone()))
+            .build();
+        register_provider(provider.tracer("test"));
+
+        let (tx, mut rx) = mpsc::channel::<SinkMessage<()>>(1);
+        remote_sinks::register(&tx);
+        let remote = flushed.clone();
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if let SinkMessage::Flush(done) = message {
+                    remote.push("remote");
+                    let _ = done.send(());
+                }
+            }
+        });
+
+        // The worker flushes the file once it is shut down by the guard.
+        let (_writer, guard) = NonBlockingBuilder::default().finish(MockFile(flushed.clone()));
+
+        shutdown_with_guards(vec![guard], Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let mut order = flushed.0.lock().unwrap().clone();
+        // The worker flushes again once it has stopped.
+        order.dedup();
+        assert_eq!(order, vec!["traces", "remote", "files"]);
+    }
+}
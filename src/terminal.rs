@@ -0,0 +1,27 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::IsTerminal;
+
+/// Whether stdout is a terminal, e.g. to color the output of the application
+/// the same way the logs are.
+pub fn is_terminal_stdout() -> bool {
+    io::stdout().is_terminal()
+}
+
+/// Whether stderr is a terminal. The stderr lines have ANSI colors only then.
+pub fn is_terminal_stderr() -> bool {
+    io::stderr().is_terminal()
+}
@@ -0,0 +1,258 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::fmt;
+use std::io;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::DefaultGuard;
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+
+use crate::logging::fmt_layer;
+use crate::Config;
+
+/// A timer always writing the same timestamp, to assert exact log lines in tests, e.g.
+/// `Config { timer: Some(CustomTimer::new(FixedTime::new("2022-10-01T08:00:00Z"))), .. }`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixedTime(pub String);
+
+impl FixedTime {
+    pub fn new(ts: &str) -> Self {
+        Self(ts.to_string())
+    }
+}
+
+impl FormatTime for FixedTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        w.write_str(&self.0)
+    }
+}
+
+/// A shared in-memory buffer receiving the exact formatted bytes, for snapshot tests.
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// The bytes written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// The bytes written so far, as a `String`.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+    }
+}
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for SharedBuffer {
+    type Writer = SharedBuffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Install a subscriber for the current thread, writing the events in
+/// `cfg.stderr.format` and `cfg.stderr.level` to the returned buffer.
+///
+/// Lines have no ANSI colors, set `cfg.timer` to a `FixedTime` for
+/// reproducible timestamps. The subscriber is removed when the guard is dropped.
+pub fn init_buffer_capture(cfg: &Config) -> (DefaultGuard, SharedBuffer) {
+    let buffer = SharedBuffer::default();
+    let layer = fmt_layer(cfg, cfg.stderr.format, cfg.resolved_timer(), buffer.clone(), false)
+        .with_filter(EnvFilter::new(&cfg.stderr.level));
+    let guard = tracing::subscriber::set_default(Registry::default().with(layer));
+    (guard, buffer)
+}
+
+/// Env var teeing the events captured by `init_test_logging` to stderr.
+pub const TEST_LOG_ENV: &str = "TEST_LOG";
+
+/// Same as `init_buffer_capture`, but with `TEST_LOG` set, e.g. `TEST_LOG=1`,
+/// the captured events are also written to stderr, to debug a failing test.
+///
+/// They are written with `eprint!`, thus captured by the test harness like the
+/// other output of the test: shown if it fails, or right away with `--nocapture`.
+pub fn init_test_logging(cfg: &Config) -> (DefaultGuard, SharedBuffer) {
+    let buffer = SharedBuffer::default();
+    let layer = fmt_layer(cfg, cfg.stderr.format, cfg.resolved_timer(), buffer.clone(), false)
+        .with_filter(EnvFilter::new(&cfg.stderr.level));
+
+    let tee = tees_to_stderr(env::var(TEST_LOG_ENV).ok().as_deref()).then(|| {
+        fmt_layer(cfg, cfg.stderr.format, cfg.resolved_timer(), TestStderr, false)
+            .with_filter(EnvFilter::new(&cfg.stderr.level))
+    });
+
+    let subscriber = Registry::default().with(layer).with(tee);
+    let guard = tracing::subscriber::set_default(subscriber);
+    (guard, buffer)
+}
+
+/// Whether `init_test_logging` tees to stderr with `TEST_LOG` set to `value`:
+/// unset, empty and `0` do not.
+fn tees_to_stderr(value: Option<&str>) -> bool {
+    matches!(value, Some(v) if !v.is_empty() && v != "0")
+}
+
+/// Writes to stderr through `eprint!`, which the test harness captures,
+/// unlike `io::stderr`.
+#[derive(Clone, Copy, Debug, Default)]
+struct TestStderr;
+
+impl io::Write for TestStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        eprint!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for TestStderr {
+    type Writer = TestStderr;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
+
+/// The ERROR and WARN events counted by `count_errors`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+/// Run `f` with a subscriber for the current thread counting the ERROR and
+/// WARN events it emits, so that a test can assert there was none, e.g.
+/// `assert_eq!(count_errors(|| migrate()).1.errors, 0)`.
+///
+/// Less verbose events are disabled, nothing is formatted. Events emitted by
+/// other threads, e.g. spawned tokio tasks, are not counted.
+pub fn count_errors<R>(f: impl FnOnce() -> R) -> (R, ErrorCounts) {
+    let counter = ErrorCounter::default();
+    let subscriber = Registry::default().with(counter.clone().with_filter(LevelFilter::WARN));
+    let result = tracing::subscriber::with_default(subscriber, f);
+    let counts = ErrorCounts {
+        errors: counter.errors.load(Ordering::Relaxed),
+        warnings: counter.warnings.load(Ordering::Relaxed),
+    };
+    (result, counts)
+}
+
+#[derive(Clone, Default)]
+struct ErrorCounter {
+    errors: Arc<AtomicUsize>,
+    warnings: Arc<AtomicUsize>,
+}
+
+impl<S: Subscriber> Layer<S> for ErrorCounter {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        match *event.metadata().level() {
+            Level::ERROR => self.errors.fetch_add(1, Ordering::Relaxed),
+            Level::WARN => self.warnings.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::error;
+    use tracing::info;
+    use tracing::warn;
+
+    use super::*;
+    use crate::CustomTimer;
+    use crate::LogFormat;
+
+    fn text_config() -> Config {
+        let mut cfg = Config::default();
+        cfg.stderr.format = LogFormat::Text;
+        cfg.stderr.level = "INFO".to_string();
+        cfg.timer = Some(CustomTimer::new(FixedTime::new("2022-10-01T08:00:00Z")));
+        cfg
+    }
+
+    #[test]
+    fn test_buffer_capture_snapshot() {
+        let (guard, buffer) = init_buffer_capture(&text_config());
+        info!(query_id = 42, "query started");
+        tracing::debug!("not captured");
+        drop(guard);
+
+        assert_eq!(
+            buffer.to_string_lossy(),
+            "2022-10-01T08:00:00Z  INFO common_tracing::test_support::tests: query started query_id=42\n"
+        );
+    }
+
+    #[test]
+    fn test_count_errors() {
+        let (result, counts) = count_errors(|| {
+            error!("failed");
+            warn!("retrying");
+            warn!("retrying");
+            info!("done");
+            7
+        });
+        assert_eq!(result, 7);
+        assert_eq!(counts, ErrorCounts {
+            errors: 1,
+            warnings: 2
+        });
+    }
+
+    #[test]
+    fn test_tees_to_stderr() {
+        assert!(!tees_to_stderr(None));
+        assert!(!tees_to_stderr(Some("")));
+        assert!(!tees_to_stderr(Some("0")));
+        assert!(tees_to_stderr(Some("1")));
+
+        // Captured either way.
+        let (guard, buffer) = init_test_logging(&text_config());
+        info!("captured");
+        drop(guard);
+        assert!(buffer.to_string_lossy().contains("captured"));
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormatEvent;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::FormattedFields;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::AnsiTheme;
+use crate::LevelStyle;
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Formats events as the text lines of `tracing_subscriber`, colored by an
+/// [`AnsiTheme`]:
+///
+/// `2022-10-01T08:00:00.000000Z  INFO req{id=1}:query: foo::bar: hello world rows=3`
+///
+/// Without ANSI escapes, e.g. when written to a buffer, the lines are not colored.
+/// Levels are written in full, use `ThemedText::with_level_style` to shorten them.
+/// Span ids are not written, use `ThemedText::with_span_ids` to write them after
+/// the span names, e.g. `req#1{id=1}:query#2:`.
+#[derive(Clone, Debug)]
+pub struct ThemedText<T> {
+    timer: T,
+    theme: AnsiTheme,
+    level_style: LevelStyle,
+    span_ids: Option<SpanIdRadix>,
+}
+
+/// How `ThemedText` writes the span ids of the scope.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpanIdRadix {
+    /// `query#1f`.
+    #[default]
+    Hex,
+    /// `query#31`.
+    Decimal,
+}
+
+impl<T> ThemedText<T> {
+    pub fn new(timer: T, theme: AnsiTheme) -> Self {
+        Self {
+            timer,
+            theme,
+            level_style: LevelStyle::default(),
+            span_ids: None,
+        }
+    }
+
+    pub fn with_level_style(mut self, level_style: LevelStyle) -> Self {
+        self.level_style = level_style;
+        self
+    }
+
+    /// Write the span ids in `radix`, or no span ids if `None`.
+    pub fn with_span_ids(mut self, radix: Option<SpanIdRadix>) -> Self {
+        self.span_ids = radix;
+        self
+    }
+}
+
+impl<S, N, T> FormatEvent<S, N> for ThemedText<T>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    T: FormatTime,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let ansi = writer.has_ansi_escapes();
+        let (dim, undim) = if ansi && self.theme.dim_metadata {
+            (DIM, RESET)
+        } else {
+            ("", "")
+        };
+
+        write!(writer, "{}", dim)?;
+        self.timer.format_time(&mut writer)?;
+        write!(writer, "{} ", undim)?;
+
+        let level = self.level_style.render(meta.level());
+        let width = self.level_style.width();
+        if ansi {
+            let color = self.theme.color(meta.level()).escape();
+            write!(writer, "{}{:>width$}{} ", color, level, RESET, width = width)?;
+        } else {
+            write!(writer, "{:>width$} ", level, width = width)?;
+        }
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                write!(writer, "{}", span.name())?;
+                let id = span.id().into_u64();
+                match self.span_ids {
+                    Some(SpanIdRadix::Hex) => write!(writer, "#{:x}", id)?,
+                    Some(SpanIdRadix::Decimal) => write!(writer, "#{}", id)?,
+                    None => {}
+                }
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, "{{{}}}", fields)?;
+                    }
+                }
+                write!(writer, ":")?;
+            }
+            write!(writer, " ")?;
+        }
+
+        write!(writer, "{}{}:{} ", dim, meta.target(), undim)?;
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use tracing::info;
+    use tracing::info_span;
+    use tracing::warn;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn format_lines(format: ThemedText<()>, f: impl FnOnce()) -> String {
+        let buf = Buffer::default();
+        let writer = buf.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .event_format(format)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let lines = buf.0.lock().unwrap().clone();
+        String::from_utf8(lines).unwrap()
+    }
+
+    fn format_levels(level_style: LevelStyle) -> String {
+        let format = ThemedText::new((), AnsiTheme::default()).with_level_style(level_style);
+        format_lines(format, || {
+            info!("started");
+            warn!("slow");
+        })
+    }
+
+    /// The line of an event in a `req` span, and the id of the span.
+    fn format_span_ids(radix: Option<SpanIdRadix>) -> (String, u64) {
+        let format = ThemedText::new((), AnsiTheme::default()).with_span_ids(radix);
+        let mut id = 0;
+        let line = format_lines(format, || {
+            // Open spans keep their ids, thus the id of `req` has two digits.
+            let _open = (0..16).map(|_| info_span!("other")).collect::<Vec<_>>();
+            let span = info_span!("req", n = 1).entered();
+            id = span.id().unwrap().into_u64();
+            info!("started");
+        });
+        (line, id)
+    }
+
+    #[test]
+    fn test_level_style() {
+        let target = module_path!();
+        assert_eq!(
+            format_levels(LevelStyle::Full),
+            format!("  INFO {0}: started\n  WARN {0}: slow\n", target)
+        );
+        assert_eq!(
+            format_levels(LevelStyle::Short),
+            format!(" I {0}: started\n W {0}: slow\n", target)
+        );
+        assert_eq!(
+            format_levels(LevelStyle::Lowercase),
+            format!("  info {0}: started\n  warn {0}: slow\n", target)
+        );
+    }
+
+    #[test]
+    fn test_span_ids() {
+        let target = module_path!();
+        let (line, _) = format_span_ids(None);
+        assert_eq!(line, format!("  INFO req{{n=1}}: {}: started\n", target));
+
+        let (line, id) = format_span_ids(Some(SpanIdRadix::Hex));
+        assert!(id > 16);
+        assert_eq!(line, format!("  INFO req#{:x}{{n=1}}: {}: started\n", id, target));
+
+        let (line, id) = format_span_ids(Some(SpanIdRadix::Decimal));
+        assert_eq!(line, format!("  INFO req#{}{{n=1}}: {}: started\n", id, target));
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use opentelemetry::global;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::sdk::export::trace::SpanExporter;
+use opentelemetry::sdk::propagation::BaggagePropagator;
+use opentelemetry::sdk::propagation::TextMapCompositePropagator;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace;
+use opentelemetry::sdk::trace::Sampler;
+use opentelemetry::sdk::trace::ShouldSample;
+use opentelemetry::sdk::trace::Tracer;
+use opentelemetry::sdk::trace::TracerProvider;
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceError;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::SpanExporterBuilder;
+use opentelemetry_otlp::TonicExporterBuilder;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT;
+use tonic::transport::Channel;
+use tonic::transport::Endpoint;
+
+use crate::export_retry::RetryExporter;
+use crate::logging::try_new_rolling_appender;
+use crate::otlp_json_file::OtlpJsonFileExporter;
+use crate::Config;
+use crate::Propagator;
+use crate::TraceConfig;
+
+/// Providers of the installed tracers, kept to flush them on shutdown.
+static TRACER_PROVIDERS: Lazy<Mutex<Vec<TracerProvider>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Install a batch tracer exporting to the endpoint found in env vars or `cfg`.
+///
+/// Returns the endpoint together with the install result,
+/// or `None` if no exporter endpoint is configured. The mirrors failing to
+/// build are skipped, with a message pushed to `warnings`.
+///
+/// The standard OpenTelemetry env vars take precedence over the Databend ones:
+///
+/// - Exporter: `OTEL_EXPORTER_OTLP_ENDPOINT`(OTLP over gRPC), then
+///   `TraceConfig::jaeger_collector_endpoint`(jaeger collector over HTTP), then
+///   `DATABEND_JAEGER_AGENT_ENDPOINT`(jaeger agent over UDP), then
+///   `TraceConfig::otlp_json_dir`(OTLP JSON files). Spans are also sent to the
+///   `TraceConfig::mirror_otlp_endpoints` of this exporter.
+/// - Service name: `OTEL_SERVICE_NAME`, then the `name` passed to `init_logging`.
+/// - Sampler: `TraceConfig::sampler`, then `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`,
+///   then `parentbased_always_on`. The env samplers are made parent based by
+///   `TraceConfig::honor_parent_sampling`, unless `OTEL_TRACES_SAMPLER` is an
+///   explicit `always_on`, `always_off` or `traceidratio`.
+pub(crate) fn install_tracer(
+    name: &str,
+    cfg: &Config,
+    warnings: &mut Vec<String>,
+) -> Option<(String, Result<Tracer, TraceError>)> {
+    let installed = install_exporter(name, cfg, warnings);
+    installed.map(|(endpoint, tracer)| (endpoint, tracer.map(register_provider)))
+}
+
+fn install_exporter(
+    name: &str,
+    cfg: &Config,
+    warnings: &mut Vec<String>,
+) -> Option<(String, Result<Tracer, TraceError>)> {
+    let service_name = non_empty_env("OTEL_SERVICE_NAME").unwrap_or_else(|| name.to_string());
+    // The pipelines of the exporter crates can not wrap their exporter or add
+    // another one, the provider is built here instead.
+    // Global fields are exported as resource attributes, jaeger shows them as process tags.
+    let mut resource_attrs: Vec<KeyValue> = cfg
+        .global_fields
+        .iter()
+        .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+        .collect();
+    let trace_config = new_trace_config(cfg, resource_attrs.clone());
+    resource_attrs.push(KeyValue::new("service.name", service_name.clone()));
+    let provider_config = new_trace_config(cfg, resource_attrs);
+
+    if let Some(endpoint) = non_empty_env("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        let tracer = SpanExporterBuilder::from(new_otlp_exporter(&endpoint, cfg))
+            .build_span_exporter()
+            .and_then(|exporter| install_batch_exporters(exporter, provider_config, cfg, warnings));
+
+        return Some((endpoint, tracer));
+    }
+
+    // The HTTP collector has no UDP packet size limit, thus never rejects large batches.
+    // Its pipeline does not expose the exporter: failed batches are neither
+    // retried nor counted, and the spans are not mirrored.
+    if !cfg.trace.jaeger_collector_endpoint.is_empty() {
+        let endpoint = cfg.trace.jaeger_collector_endpoint.clone();
+        let tracer = opentelemetry_jaeger::new_collector_pipeline()
+            .with_service_name(service_name)
+            .with_endpoint(&endpoint)
+            .with_reqwest()
+            .with_trace_config(trace_config)
+            .install_batch(opentelemetry::runtime::Tokio);
+
+        return Some((endpoint, tracer));
+    }
+
+    if let Some(endpoint) = non_empty_env("DATABEND_JAEGER_AGENT_ENDPOINT") {
+        let mut pipeline = opentelemetry_jaeger::new_agent_pipeline()
+            .with_service_name(service_name)
+            .with_endpoint(&endpoint)
+            .with_auto_split_batch(true)
+            .with_trace_config(trace_config);
+        if cfg.trace.jaeger_max_packet_size > 0 {
+            pipeline = pipeline.with_max_packet_size(cfg.trace.jaeger_max_packet_size);
+        }
+        let tracer = pipeline
+            .build_async_agent_exporter(opentelemetry::runtime::Tokio)
+            .and_then(|exporter| install_batch_exporters(exporter, provider_config, cfg, warnings));
+
+        return Some((endpoint, tracer));
+    }
+
+    if !cfg.trace.otlp_json_dir.is_empty() {
+        let dir = cfg.trace.otlp_json_dir.clone();
+        let prefix = format!("{}.spans", name);
+        let appender = match try_new_rolling_appender(&dir, &prefix, &cfg.file) {
+            Ok(appender) => appender,
+            Err(e) => return Some((dir, Err(TraceError::Other(Box::new(e))))),
+        };
+        let exporter = OtlpJsonFileExporter::new(appender, &service_name, &cfg.global_fields);
+        let tracer = install_batch_exporters(exporter, trace_config, cfg, warnings);
+
+        return Some((dir, tracer));
+    }
+
+    None
+}
+
+fn new_otlp_exporter(endpoint: &str, cfg: &Config) -> TonicExporterBuilder {
+    let mut exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    if let Some(channel) = new_otlp_channel(endpoint, cfg) {
+        exporter = exporter.with_channel(channel);
+    }
+    exporter
+}
+
+/// A channel to `endpoint` connecting within `otlp_connect_timeout_secs` and
+/// kept alive as configured by `cfg`, connecting on the first export. `None` if
+/// the endpoint is invalid, the exporter then builds its own channel, reporting
+/// the invalid endpoint.
+///
+/// The channel reconnects by itself once the connection is closed.
+fn new_otlp_channel(endpoint: &str, cfg: &Config) -> Option<Channel> {
+    let trace = &cfg.trace;
+    let endpoint = Endpoint::from_shared(endpoint.to_string())
+        .ok()?
+        .timeout(Duration::from_secs(OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT))
+        .connect_timeout(Duration::from_secs(trace.otlp_connect_timeout_secs));
+    if trace.otlp_keepalive_interval_secs == 0 {
+        return Some(endpoint.connect_lazy());
+    }
+
+    let interval = Duration::from_secs(trace.otlp_keepalive_interval_secs);
+    let channel = endpoint
+        .tcp_keepalive(Some(interval))
+        .http2_keep_alive_interval(interval)
+        .keep_alive_timeout(Duration::from_secs(trace.otlp_keepalive_timeout_secs))
+        .keep_alive_while_idle(true)
+        .connect_lazy();
+    Some(channel)
+}
+
+/// The trace config with the sampler of `cfg` and the resource `attrs`.
+///
+/// With the `resource-detection` feature, the detected attributes are added,
+/// `attrs` take precedence.
+fn new_trace_config(cfg: &Config, attrs: Vec<KeyValue>) -> trace::Config {
+    let mut trace_config = trace::config();
+    trace_config.sampler = configured_sampler(&cfg.trace, sampler_from_env());
+
+    let resource = Resource::new(attrs);
+    #[cfg(feature = "resource-detection")]
+    let resource = crate::resource::detected_resource().merge(&resource);
+    if !resource.is_empty() {
+        trace_config = trace_config.with_resource(resource);
+    }
+    trace_config
+}
+
+/// Install a batch tracer exporting with `exporter` and to every
+/// `TraceConfig::mirror_otlp_endpoints`, retrying failed exports up to
+/// `TraceConfig::export_max_retries` times. Batches still failing are counted
+/// by `trace_export_failures`, even without retries.
+///
+/// A mirror failing to build is skipped, the others and `exporter` are kept.
+fn install_batch_exporters<E: SpanExporter + 'static>(
+    exporter: E,
+    trace_config: trace::Config,
+    cfg: &Config,
+    warnings: &mut Vec<String>,
+) -> Result<Tracer, TraceError> {
+    let retries = cfg.trace.export_max_retries;
+    let mut builder = with_batch_exporter(TracerProvider::builder(), exporter, retries);
+    for endpoint in &cfg.trace.mirror_otlp_endpoints {
+        match SpanExporterBuilder::from(new_otlp_exporter(endpoint, cfg)).build_span_exporter() {
+            Ok(mirror) => builder = with_batch_exporter(builder, mirror, retries),
+            Err(e) => warnings.push(format!(
+                "failed to build the mirror exporter for {}, spans are not mirrored to it: {}",
+                endpoint, e
+            )),
+        }
+    }
+
+    let provider = builder.with_config(trace_config).build();
+    let tracer =
+        provider.versioned_tracer("common_tracing", Some(env!("CARGO_PKG_VERSION")), None);
+    // The global provider keeps the provider alive, the tracer only refers to it.
+    let _ = global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+fn with_batch_exporter<E: SpanExporter + 'static>(
+    builder: trace::Builder,
+    exporter: E,
+    max_retries: usize,
+) -> trace::Builder {
+    let exporter = RetryExporter::new(exporter, max_retries);
+    builder.with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+}
+
+pub(crate) fn register_provider(tracer: Tracer) -> Tracer {
+    if let Some(provider) = tracer.provider() {
+        TRACER_PROVIDERS.lock().unwrap().push(provider);
+    }
+    tracer
+}
+
+/// Build the propagator of `kind`, set as the global one by `init_logging`.
+pub(crate) fn new_propagator(kind: Propagator) -> TextMapCompositePropagator {
+    let propagators: Vec<Box<dyn TextMapPropagator + Send + Sync>> = match kind {
+        Propagator::TraceContext => vec![Box::new(TraceContextPropagator::new())],
+        Propagator::Baggage => vec![Box::new(BaggagePropagator::new())],
+        Propagator::Composite => vec![
+            Box::new(TraceContextPropagator::new()),
+            Box::new(BaggagePropagator::new()),
+        ],
+    };
+    TextMapCompositePropagator::new(propagators)
+}
+
+/// The flush did not finish in time, some spans or remote log events may be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushTimeout(pub Duration);
+
+impl Display for FlushTimeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "flushing timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for FlushTimeout {}
+
+/// Export all finished but not yet exported spans, waiting at most `timeout`.
+///
+/// Call it during graceful shutdown, before the process exits, so that the
+/// last spans are not lost. Export errors are reported to the OpenTelemetry
+/// error handler, only running out of time is an error here.
+pub async fn flush_traces(timeout: Duration) -> Result<(), FlushTimeout> {
+    let providers = TRACER_PROVIDERS.lock().unwrap().clone();
+    if providers.is_empty() {
+        return Ok(());
+    }
+
+    // `force_flush` blocks until the batch processor is done.
+    let flush = tokio::task::spawn_blocking(move || {
+        for provider in providers {
+            for result in provider.force_flush() {
+                if let Err(e) = result {
+                    opentelemetry::global::handle_error(e);
+                }
+            }
+        }
+    });
+
+    match tokio::time::timeout(timeout, flush).await {
+        Ok(_) => Ok(()),
+        Err(_elapsed) => Err(FlushTimeout(timeout)),
+    }
+}
+
+/// The sampler of `cfg` if any, used as is. Otherwise `from_env`, returned by
+/// `sampler_from_env`, made parent based by `honor_parent_sampling`.
+fn configured_sampler(cfg: &TraceConfig, from_env: (Sampler, bool)) -> Box<dyn ShouldSample> {
+    if let Some(sampler) = &cfg.sampler {
+        return Box::new(sampler.clone());
+    }
+
+    let (sampler, explicit) = from_env;
+    // A parent based sampler only samples root spans, wrapping one again changes nothing.
+    if cfg.honor_parent_sampling && !explicit {
+        Box::new(Sampler::ParentBased(Box::new(sampler)))
+    } else {
+        Box::new(sampler)
+    }
+}
+
+/// Build the sampler from `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`,
+/// see `sampler_from`.
+fn sampler_from_env() -> (Sampler, bool) {
+    sampler_from(
+        non_empty_env("OTEL_TRACES_SAMPLER").as_deref(),
+        non_empty_env("OTEL_TRACES_SAMPLER_ARG").as_deref(),
+    )
+}
+
+/// Build the sampler `name` with the argument `arg`, with the values of
+/// `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG` defined by the
+/// OpenTelemetry specification.
+///
+/// Unknown or absent values fall back to `parentbased_always_on`.
+/// Also returns whether an explicit `always_on`, `always_off` or
+/// `traceidratio` was set, which ignores the parent decision by definition.
+fn sampler_from(name: Option<&str>, arg: Option<&str>) -> (Sampler, bool) {
+    let ratio = || arg.and_then(|arg| arg.parse::<f64>().ok()).unwrap_or(1.0);
+
+    let sampler = match name {
+        Some("always_on") => return (Sampler::AlwaysOn, true),
+        Some("always_off") => return (Sampler::AlwaysOff, true),
+        Some("traceidratio") => return (Sampler::TraceIdRatioBased(ratio()), true),
+        Some("parentbased_always_off") => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+        Some("parentbased_traceidratio") => {
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio())))
+        }
+        _ => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+    };
+    (sampler, false)
+}
+
+fn non_empty_env(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::ready;
+    use std::future::Future;
+    use std::pin::Pin;
+
+    use opentelemetry::sdk::export::trace::ExportResult;
+    use opentelemetry::sdk::export::trace::SpanData;
+    use opentelemetry::trace::SamplingDecision;
+    use opentelemetry::trace::SpanContext;
+    use opentelemetry::trace::SpanId;
+    use opentelemetry::trace::SpanKind;
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry::trace::TraceFlags;
+    use opentelemetry::trace::TraceId;
+    use opentelemetry::trace::TraceState;
+    use opentelemetry::Context;
+    use opentelemetry::InstrumentationLibrary;
+
+    use crate::CustomSampler;
+
+    use super::*;
+
+    /// Sampled by a 0.5 ratio.
+    const TRACE_ID: TraceId = TraceId::from_bytes([1; 16]);
+
+    /// The decisions of the sampler of `cfg` with `OTEL_TRACES_SAMPLER=name`
+    /// and `OTEL_TRACES_SAMPLER_ARG=0.5`, for a child of a sampled and of a
+    /// non-sampled parent.
+    fn child_decisions(cfg: &TraceConfig, name: &str) -> (SamplingDecision, SamplingDecision) {
+        let sampler = configured_sampler(cfg, sampler_from(Some(name), Some("0.5")));
+
+        let decision = |flags| {
+            let parent = SpanContext::new(
+                TRACE_ID,
+                SpanId::from_bytes([1; 8]),
+                flags,
+                true,
+                TraceState::default(),
+            );
+            let cx = Context::new().with_remote_span_context(parent);
+            sampler
+                .should_sample(
+                    Some(&cx),
+                    TRACE_ID,
+                    "child",
+                    &SpanKind::Internal,
+                    &Default::default(),
+                    &[],
+                    &InstrumentationLibrary::new("test", None, None),
+                )
+                .decision
+        };
+        (decision(TraceFlags::SAMPLED), decision(TraceFlags::default()))
+    }
+
+    #[test]
+    fn test_honor_parent_sampling() {
+        use SamplingDecision::Drop;
+        use SamplingDecision::RecordAndSample;
+
+        let cfg = TraceConfig::default();
+        assert!(cfg.honor_parent_sampling);
+
+        // Explicit samplers ignore the parent.
+        assert_eq!(child_decisions(&cfg, "always_on"), (RecordAndSample, RecordAndSample));
+        assert_eq!(child_decisions(&cfg, "always_off"), (Drop, Drop));
+        assert_eq!(child_decisions(&cfg, "traceidratio"), (RecordAndSample, RecordAndSample));
+
+        // The others follow it.
+        assert_eq!(child_decisions(&cfg, "parentbased_always_off"), (RecordAndSample, Drop));
+        assert_eq!(child_decisions(&cfg, "parentbased_traceidratio"), (RecordAndSample, Drop));
+        assert_eq!(child_decisions(&cfg, ""), (RecordAndSample, Drop));
+
+        // A custom sampler is used as is.
+        let cfg = TraceConfig {
+            sampler: Some(CustomSampler::new(Sampler::AlwaysOn)),
+            ..TraceConfig::default()
+        };
+        assert_eq!(child_decisions(&cfg, ""), (RecordAndSample, RecordAndSample));
+    }
+
+    #[test]
+    fn test_unwritable_otlp_json_dir() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut cfg = Config::default();
+        cfg.trace.otlp_json_dir = file.path().join("spans").to_string_lossy().to_string();
+
+        let installed = install_exporter("test", &cfg, &mut vec![]);
+        assert!(matches!(installed, Some((_, Err(_)))));
+    }
+
+    #[derive(Debug)]
+    struct NoopExporter;
+
+    impl SpanExporter for NoopExporter {
+        fn export(
+            &mut self,
+            _batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            Box::pin(ready(Ok(())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_mirror_is_skipped() {
+        let mut cfg = Config::default();
+        cfg.trace.mirror_otlp_endpoints = vec![
+            "not an endpoint".to_string(),
+            "http://127.0.0.1:4317".to_string(),
+        ];
+
+        let mut warnings = vec![];
+        let tracer = install_batch_exporters(NoopExporter, trace::config(), &cfg, &mut warnings);
+        assert!(tracer.is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("not an endpoint"));
+    }
+}
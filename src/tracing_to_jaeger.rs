@@ -15,7 +15,14 @@
 use opentelemetry::global;
 use opentelemetry::propagation::Extractor;
 use opentelemetry::propagation::Injector;
+use opentelemetry::trace::SpanContext;
+use opentelemetry::trace::TraceContextExt;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::SpanRef;
+
+use crate::baggage::current_context;
 
 /// Inject tracing info into tonic request meta.
 struct MetadataMapInjector<'a>(&'a mut tonic::metadata::MetadataMap);
@@ -60,8 +67,7 @@ impl<'a> Extractor for MetadataMapExtractor<'a> {
 ///
 /// The global propagater must be installed, e.g. by calling: TODO
 pub fn inject_span_to_tonic_request<T>(mes: impl tonic::IntoRequest<T>) -> tonic::Request<T> {
-    let curr = tracing::Span::current();
-    let cx = curr.context();
+    let cx = current_context();
 
     let mut request = mes.into_request();
 
@@ -87,3 +93,55 @@ pub fn extract_remote_span_as_parent<T>(request: &tonic::Request<T>) {
     let span = tracing::Span::current();
     span.set_parent(parent_cx);
 }
+
+/// Extract the remote span context from tonic request meta, e.g. to link a
+/// batch span to the spans of the requests it processes with `with_links`.
+///
+/// Returns an invalid context if the request carries none.
+pub fn extract_remote_span_context<T>(request: &tonic::Request<T>) -> SpanContext {
+    let cx = global::get_text_map_propagator(|prop| {
+        prop.extract(&MetadataMapExtractor(request.metadata()))
+    });
+    cx.span().span_context().clone()
+}
+
+/// Add OpenTelemetry links from `span` to the `contexts`, e.g. from a fan-in
+/// batch span to the N upstream traces, since `tracing` has no links.
+///
+/// Links must be added before the span is closed, invalid contexts are skipped.
+///
+/// ```ignore
+/// let span = with_links(info_span!("batch"), requests.iter().map(extract_remote_span_context));
+/// ```
+pub fn with_links(
+    span: tracing::Span,
+    contexts: impl IntoIterator<Item = SpanContext>,
+) -> tracing::Span {
+    for cx in contexts {
+        if cx.is_valid() {
+            span.add_link(cx);
+        }
+    }
+    span
+}
+
+/// Returns the OpenTelemetry `(trace_id, span_id)` of a span as bytes,
+/// if the span is tracked by the OpenTelemetry layer.
+#[allow(dead_code)]
+pub(crate) fn otel_ids<'a, S>(span: &SpanRef<'a, S>) -> Option<([u8; 16], [u8; 8])>
+where S: LookupSpan<'a> {
+    let extensions = span.extensions();
+    let data = extensions.get::<OtelData>()?;
+
+    // Only root spans have their trace id in the builder, others inherit it from the parent.
+    let parent = data.parent_cx.span();
+    let trace_id = data
+        .builder
+        .trace_id
+        .unwrap_or_else(|| parent.span_context().trace_id());
+    let span_id = data.builder.span_id?;
+
+    let trace_id = u128::from_str_radix(&trace_id.to_string(), 16).ok()?;
+    let span_id = u64::from_str_radix(&span_id.to_string(), 16).ok()?;
+    Some((trace_id.to_be_bytes(), span_id.to_be_bytes()))
+}
@@ -0,0 +1,197 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::Event;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::level_override::MessageVisitor;
+use crate::remote_sinks;
+use crate::remote_sinks::FailureReporter;
+use crate::remote_sinks::RemoteSinkError;
+use crate::remote_sinks::SinkMessage;
+use crate::WebhookConfig;
+
+/// Max number of events posted in one request.
+const MAX_BATCH_SIZE: usize = 20;
+
+/// Max number of events waiting for delivery, new events are dropped when full.
+const MAX_QUEUE_SIZE: usize = 1024;
+
+/// How long a partial batch waits before it is posted.
+const SCHEDULED_DELAY: Duration = Duration::from_secs(2);
+
+/// Timeout of a single request to the webhook.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+struct WebhookEvent {
+    level: &'static str,
+    target: &'static str,
+    message: String,
+}
+
+/// A layer posting events as JSON to a webhook, a cheap alerting path.
+///
+/// Events are queued and posted in batches by a background tokio task,
+/// thus this must be created inside a tokio runtime, `new` fails otherwise. Every request has a
+/// Slack-compatible `text`, one line per event, and the events in `events`:
+///
+/// `{"text":"databend-query WARN foo::bar: disk almost full free=3%","events":[{"level":"WARN","target":"foo::bar","message":"disk almost full free=3%"}]}`
+///
+/// At most `WebhookConfig::max_events_per_minute` events are posted, the
+/// others are dropped and their number is reported with the next batch.
+/// Failed requests are not retried. Logging never blocks on the webhook.
+///
+/// The queued events are posted by `shutdown_logging`.
+pub struct WebhookLayer {
+    tx: mpsc::Sender<SinkMessage<WebhookEvent>>,
+}
+
+impl WebhookLayer {
+    pub fn new(
+        service_name: &str,
+        cfg: &WebhookConfig,
+    ) -> Result<Self, RemoteSinkError<reqwest::Error>> {
+        let runtime = remote_sinks::runtime()?;
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(RemoteSinkError::Client)?;
+
+        let (tx, rx) = mpsc::channel(MAX_QUEUE_SIZE);
+        remote_sinks::register(&tx);
+        runtime.spawn(post_loop(
+            client,
+            cfg.url.clone(),
+            service_name.to_string(),
+            cfg.max_events_per_minute,
+            rx,
+        ));
+
+        Ok(Self { tx })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for WebhookLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        // Drop the event if the webhook can not keep up.
+        let _ = self.tx.try_send(SinkMessage::Item(WebhookEvent {
+            level: meta.level().as_str(),
+            target: meta.target(),
+            message: visitor.message,
+        }));
+    }
+}
+
+async fn post_loop(
+    client: reqwest::Client,
+    url: String,
+    service_name: String,
+    max_per_minute: usize,
+    mut rx: mpsc::Receiver<SinkMessage<WebhookEvent>>,
+) {
+    let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+    let mut window_start = Instant::now();
+    let mut sent_in_window = 0;
+    let mut dropped = 0;
+    let mut failures = FailureReporter::new("post logs to webhook");
+
+    loop {
+        let mut flushed = None;
+        let closed = match tokio::time::timeout(SCHEDULED_DELAY, rx.recv()).await {
+            Ok(Some(SinkMessage::Flush(done))) => {
+                flushed = Some(done);
+                false
+            }
+            Ok(Some(SinkMessage::Item(event))) => {
+                if window_start.elapsed() >= RATE_WINDOW {
+                    window_start = Instant::now();
+                    sent_in_window = 0;
+                }
+                if sent_in_window < max_per_minute {
+                    sent_in_window += 1;
+                    batch.push(event);
+                } else {
+                    dropped += 1;
+                }
+                if batch.len() < MAX_BATCH_SIZE {
+                    continue;
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(_elapsed) => false,
+        };
+
+        if !batch.is_empty() {
+            let mut lines = batch
+                .iter()
+                .map(|e| format!("{} {} {}: {}", service_name, e.level, e.target, e.message))
+                .collect::<Vec<_>>();
+            if dropped > 0 {
+                lines.push(format!(
+                    "{}: {} events dropped by the rate limit",
+                    service_name, dropped
+                ));
+                dropped = 0;
+            }
+            let events = batch
+                .drain(..)
+                .map(|e| json!({ "level": e.level, "target": e.target, "message": e.message }))
+                .collect::<Vec<_>>();
+            let body = json!({ "text": lines.join("\n"), "events": events });
+
+            let result = client
+                .post(&url)
+                .header("content-type", "application/json")
+                .body(body.to_string())
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+            if let Err(e) = result {
+                failures.report(e);
+            }
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+
+        if closed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_without_runtime() {
+        let layer = WebhookLayer::new("test", &WebhookConfig::default());
+        assert!(matches!(layer, Err(RemoteSinkError::NoRuntime(_))));
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright 2022 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+use windows_sys::Win32::System::EventLog::DeregisterEventSource;
+use windows_sys::Win32::System::EventLog::EventSourceHandle;
+use windows_sys::Win32::System::EventLog::RegisterEventSourceW;
+use windows_sys::Win32::System::EventLog::ReportEventW;
+use windows_sys::Win32::System::EventLog::EVENTLOG_ERROR_TYPE;
+use windows_sys::Win32::System::EventLog::EVENTLOG_WARNING_TYPE;
+
+use crate::level_override::MessageVisitor;
+
+/// A layer writes `WARN` and `ERROR` events to the Windows Event Log,
+/// as warning and error entries of the event source named by the app.
+///
+/// Events of other levels are ignored.
+pub struct EventLogLayer {
+    handle: EventSourceHandle,
+}
+
+impl EventLogLayer {
+    pub fn new(source: &str) -> io::Result<Self> {
+        let source = to_wide(source);
+        let handle = unsafe { RegisterEventSourceW(std::ptr::null(), source.as_ptr()) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { handle })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let event_type = match *meta.level() {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => return,
+        };
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let message = to_wide(&format!("{}: {}", meta.target(), visitor.message));
+        let strings = [message.as_ptr()];
+
+        // Nowhere to report a failure to, the entry is dropped.
+        unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                std::ptr::null_mut(),
+                strings.len() as u16,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLogLayer {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
@@ -0,0 +1,47 @@
+// Copyright 2021 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use common_tracing::build_global_subscriber;
+use common_tracing::Config;
+use tracing::debug;
+use tracing::info;
+use tracing::trace;
+
+use crate::read_log_lines;
+
+#[test]
+fn test_dual_output_files_have_their_own_level() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = Config::dual_output("INFO", "DEBUG", dir.path().to_str().unwrap());
+
+    let (subscriber, outcome) = build_global_subscriber("dual", &cfg);
+    assert!(outcome.layers_enabled.file);
+    assert!(outcome.layers_enabled.stderr);
+
+    tracing::subscriber::with_default(subscriber, || {
+        info!("info line");
+        debug!(count = 5, "debug line");
+        trace!("trace line");
+    });
+    // Flush the files.
+    drop(outcome);
+
+    let lines = read_log_lines(dir.path(), "dual");
+    let messages = lines
+        .iter()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .map(|v| v["msg"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(messages, vec!["info line", "debug line"]);
+}
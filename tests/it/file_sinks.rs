@@ -0,0 +1,83 @@
+// Copyright 2021 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use common_tracing::build_global_subscriber;
+use common_tracing::Config;
+use common_tracing::FileSinkConfig;
+use common_tracing::LogFormat;
+use tracing::info;
+
+use crate::read_log_lines;
+
+fn sink(name: &str, format: LogFormat) -> FileSinkConfig {
+    FileSinkConfig {
+        name: name.to_string(),
+        targets: vec![name.to_string()],
+        level: "INFO".to_string(),
+        format,
+    }
+}
+
+#[test]
+fn test_file_sinks_write_their_own_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut cfg = Config::default();
+    cfg.file.on = true;
+    cfg.file.dir = dir.path().to_str().unwrap().to_string();
+    cfg.file.format = LogFormat::Text;
+    cfg.file.sinks = vec![
+        sink("audit", LogFormat::Json),
+        sink("access", LogFormat::Text),
+        sink("slow", LogFormat::Logfmt),
+    ];
+
+    let (subscriber, outcome) = build_global_subscriber("main", &cfg);
+    tracing::subscriber::with_default(subscriber, || {
+        info!(target: "audit", user = "alice", "login");
+        info!(target: "access", path = "/v1/query", "request");
+        info!(target: "slow", elapsed_ms = 1500, "query");
+        info!(target: "other", "main line");
+    });
+    drop(outcome);
+
+    let audit = read_log_lines(dir.path(), "audit");
+    assert_eq!(audit.len(), 1);
+    let record = serde_json::from_str::<serde_json::Value>(&audit[0]).unwrap();
+    assert_eq!(record["target"], "audit");
+    assert_eq!(record["fields"]["message"], "login");
+    assert_eq!(record["fields"]["user"], "alice");
+
+    let access = read_log_lines(dir.path(), "access");
+    assert_eq!(access.len(), 1);
+    assert!(serde_json::from_str::<serde_json::Value>(&access[0]).is_err());
+    assert!(
+        access[0].ends_with(" INFO access: request path=\"/v1/query\""),
+        "{}",
+        access[0]
+    );
+
+    let slow = read_log_lines(dir.path(), "slow");
+    assert_eq!(slow.len(), 1);
+    assert!(slow[0].starts_with("ts="), "{}", slow[0]);
+    assert!(
+        slow[0].ends_with(" level=INFO target=slow msg=query elapsed_ms=1500"),
+        "{}",
+        slow[0]
+    );
+
+    // The routed events are not written to the main file.
+    let main = read_log_lines(dir.path(), "main");
+    assert_eq!(main.len(), 1);
+    assert!(main[0].ends_with(" INFO other: main line"), "{}", main[0]);
+}
@@ -0,0 +1,34 @@
+// Copyright 2021 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::path::Path;
+
+mod dual_output;
+mod file_sinks;
+
+/// The lines of the log files of `prefix` in `dir`.
+pub(crate) fn read_log_lines(dir: &Path, prefix: &str) -> Vec<String> {
+    let mut lines = vec![];
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name.split('.').next() != Some(prefix) {
+            continue;
+        }
+        let content = fs::read_to_string(path).unwrap();
+        lines.extend(content.lines().map(|l| l.to_string()));
+    }
+    lines
+}
@@ -0,0 +1,72 @@
+// Copyright 2021 Datafuse Labs.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Not part of `tests/it`: the exporter is selected by env vars, which would
+//! leak into the other tests of the same process.
+
+use std::env;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use common_tracing::build_global_subscriber;
+use common_tracing::flush_traces;
+use common_tracing::Config;
+use common_tracing::FileConfig;
+use common_tracing::StderrConfig;
+use tracing::info_span;
+
+#[tokio::test]
+async fn test_spans_reach_the_jaeger_agent() {
+    // Stands for the jaeger agent: the spans are sent as thrift over UDP.
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    agent
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    env::set_var(
+        "DATABEND_JAEGER_AGENT_ENDPOINT",
+        agent.local_addr().unwrap().to_string(),
+    );
+    env::remove_var("OTEL_EXPORTER_OTLP_ENDPOINT");
+    env::remove_var("OTEL_SERVICE_NAME");
+    env::set_var("RUST_LOG", "info");
+
+    let cfg = Config {
+        file: FileConfig {
+            on: false,
+            ..Default::default()
+        },
+        stderr: StderrConfig {
+            on: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (subscriber, outcome) = build_global_subscriber("jaeger-it", &cfg);
+    assert!(outcome.layers_enabled.jaeger);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = info_span!("execute_query", query_id = "q-42").entered();
+    });
+    flush_traces(Duration::from_secs(10)).await.unwrap();
+
+    let mut packet = vec![0; 65536];
+    let len = agent.recv(&mut packet).unwrap();
+    let packet = &packet[..len];
+    // Thrift keeps the strings as is.
+    let contains = |s: &str| packet.windows(s.len()).any(|w| w == s.as_bytes());
+    assert!(contains("jaeger-it"), "service name not exported");
+    assert!(contains("execute_query"), "span name not exported");
+    assert!(contains("query_id") && contains("q-42"), "span tag not exported");
+}